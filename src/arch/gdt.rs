@@ -1,22 +1,29 @@
 //! Global Descriptor Table setup.
+//!
+//! The TSS holds the interrupt stack table (IST): dedicated stacks the CPU
+//! switches to when entering a handler installed with
+//! `set_stack_index(<index>)`, so faults caused by a broken kernel stack
+//! can still be handled.
 
+use core::ops::Range;
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
-/// Stack size for double fault handler.
+/// IST slot holding the double fault handler's stack.
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
-const STACK_SIZE: usize = 4096 * 5;
+
+/// Size of each IST stack.
+pub const IST_STACK_SIZE: usize = 4096 * 5;
+
+static mut DOUBLE_FAULT_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
 
 lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            stack_start + STACK_SIZE
-        };
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            VirtAddr::from_ptr(&raw const DOUBLE_FAULT_STACK) + IST_STACK_SIZE;
         tss
     };
 
@@ -35,6 +42,15 @@ struct Selectors {
     tss_selector: SegmentSelector,
 }
 
+/// Address range `[bottom, top)` of the stack in IST slot `index`, or None
+/// if that slot has no stack.
+pub fn ist_stack(index: u16) -> Option<Range<u64>> {
+    // The TSS is packed, so copy the table out rather than borrow into it
+    let table = TSS.interrupt_stack_table;
+    let top = table.get(usize::from(index))?.as_u64();
+    (top != 0).then(|| top - IST_STACK_SIZE as u64..top)
+}
+
 /// Initialize the Global Descriptor Table.
 pub fn init() {
     use x86_64::instructions::tables::load_tss;
@@ -2,6 +2,7 @@
 
 use lazy_static::lazy_static;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::{PrivilegeLevel, VirtAddr};
 use crate::println;
 
 use super::interrupts::InterruptIndex;
@@ -24,6 +25,17 @@ lazy_static! {
             .set_handler_fn(super::interrupts::timer_handler);
         idt[InterruptIndex::Keyboard.as_usize()]
             .set_handler_fn(super::interrupts::keyboard_handler);
+        // Remaining lines dispatch to callbacks claimed via `register`
+        for (irq, trampoline) in super::interrupts::IRQ_TRAMPOLINES {
+            idt[usize::from(super::interrupts::PIC_1_OFFSET + irq)].set_handler_fn(trampoline);
+        }
+
+        // System calls; callable from ring 3 once library OSes run there
+        unsafe {
+            idt[crate::syscall::SYSCALL_VECTOR]
+                .set_handler_addr(VirtAddr::new(crate::syscall::entry as extern "C" fn() as usize as u64))
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
 
         idt
     };
@@ -42,6 +54,17 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64
 ) -> ! {
+    let rsp: u64;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp) };
+    let on_ist = super::gdt::ist_stack(super::gdt::DOUBLE_FAULT_IST_INDEX)
+        .is_some_and(|stack| stack.contains(&rsp));
+    // The faulting code may hold the serial lock
+    crate::panic_println!("[FAULT] double fault handled on IST stack: {}", on_ist);
+
+    #[cfg(all(feature = "stack-overflow-demo", feature = "qemu-test"))]
+    if on_ist {
+        super::exit_qemu(super::ExitCode::Success);
+    }
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
@@ -51,9 +74,16 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
+    let addr = Cr2::read();
+
     println!();
     println!("!!! PAGE FAULT !!!");
-    println!("Accessed Address: {:?}", Cr2::read());
+    println!("Accessed Address: {:?}", addr);
+    if crate::memory::heap::is_guard_page(addr.as_u64() as usize) {
+        println!("(kernel heap guard page — heap overrun or underrun)");
+        #[cfg(all(feature = "guard-page-demo", feature = "qemu-test"))]
+        super::exit_qemu(super::ExitCode::Success);
+    }
     println!("Error Code: {:?}", error_code);
     println!("{:#?}", stack_frame);
 
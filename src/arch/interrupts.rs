@@ -3,9 +3,10 @@
 //! Remaps IRQ 0-15 to interrupt vectors 32-47 to avoid
 //! conflicts with CPU exception vectors (0-31).
 
+use pc_keyboard::DecodedKey;
 use pic8259::ChainedPics;
 use spin::Mutex;
-use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::idt::{HandlerFunc, InterruptStackFrame};
 
 /// PIC1 starts at vector 32 (right after CPU exceptions 0-31).
 pub const PIC_1_OFFSET: u8 = 32;
@@ -42,6 +43,173 @@ pub fn ticks() -> u64 {
     TICKS.load(core::sync::atomic::Ordering::Relaxed)
 }
 
+/// Block the calling (bootstrap) thread for at least `n` timer ticks,
+/// halting between interrupts. For pausing `kernel_main`; tasks should
+/// use `scheduler::sleep_current` instead.
+///
+/// Interrupts are enabled while waiting, since with them off the tick
+/// counter would never advance; if they were off on entry they are turned
+/// off again before returning.
+pub fn sleep_ticks(n: u64) {
+    use x86_64::instructions::interrupts;
+
+    let target = ticks().saturating_add(n);
+    let was_enabled = interrupts::are_enabled();
+    while ticks() < target {
+        // sti;hlt as one step, so the waking tick can't slip in between
+        interrupts::enable_and_hlt();
+    }
+    if !was_enabled {
+        interrupts::disable();
+    }
+}
+
+/// Keys dropped because the queue was full (see `dropped_key_count`).
+static DROPPED_KEYS: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+
+/// Timer ticks that arrived with no scheduler running tasks.
+static IDLE_TICKS: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+
+/// Number of keyboard events lost to a full queue since boot. A rising
+/// count means nobody is draining `read_key` fast enough.
+pub fn dropped_key_count() -> u64 {
+    DROPPED_KEYS.load(core::sync::atomic::Ordering::Relaxed)
+}
+
+/// Number of timer ticks that arrived while the scheduler had no tasks.
+pub fn idle_tick_count() -> u64 {
+    IDLE_TICKS.load(core::sync::atomic::Ordering::Relaxed)
+}
+
+/// Capacity of the keyboard event queue; new keys are dropped when full.
+pub const KEY_QUEUE_CAPACITY: usize = 128;
+
+/// Decoded keys waiting to be consumed, filled by the keyboard IRQ.
+static KEY_QUEUE: Mutex<KeyQueue> = Mutex::new(KeyQueue::new());
+
+/// Fixed-capacity FIFO ring of decoded keys.
+struct KeyQueue {
+    keys: [Option<DecodedKey>; KEY_QUEUE_CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+impl KeyQueue {
+    const fn new() -> Self {
+        Self { keys: [None; KEY_QUEUE_CAPACITY], head: 0, len: 0 }
+    }
+
+    /// Enqueue a key; returns false (dropping it) if the queue is full.
+    fn push(&mut self, key: DecodedKey) -> bool {
+        if self.len == KEY_QUEUE_CAPACITY {
+            return false;
+        }
+        self.keys[(self.head + self.len) % KEY_QUEUE_CAPACITY] = Some(key);
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<DecodedKey> {
+        if self.len == 0 {
+            return None;
+        }
+        let key = self.keys[self.head].take();
+        self.head = (self.head + 1) % KEY_QUEUE_CAPACITY;
+        self.len -= 1;
+        key
+    }
+}
+
+/// Decode a scancode set 1 byte and queue the key it completes, if any.
+///
+/// The keyboard IRQ feeds every byte it reads through here; the selftest
+/// feeds its own to simulate typing.
+pub fn feed_scancode(scancode: u8) {
+    use pc_keyboard::{layouts, HandleControl, ScancodeSet1};
+
+    lazy_static::lazy_static! {
+        static ref KEYBOARD: Mutex<pc_keyboard::Keyboard<layouts::Us104Key, ScancodeSet1>> =
+            Mutex::new(pc_keyboard::Keyboard::new(
+                ScancodeSet1::new(),
+                layouts::Us104Key,
+                HandleControl::Ignore,
+            ));
+    }
+
+    // The keyboard IRQ takes these locks too, so hold them with interrupts off
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut keyboard = KEYBOARD.lock();
+        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            if let Some(key) = keyboard.process_keyevent(key_event) {
+                // Queue for consumers; dropped if nobody is draining it
+                if !KEY_QUEUE.lock().push(key) {
+                    DROPPED_KEYS.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+                }
+            }
+        }
+    });
+}
+
+/// Take the oldest pending keyboard event, if any.
+pub fn read_key() -> Option<DecodedKey> {
+    // The keyboard IRQ takes this lock too, so hold it with interrupts off
+    x86_64::instructions::interrupts::without_interrupts(|| KEY_QUEUE.lock().pop())
+}
+
+/// Whether a keyboard event is waiting.
+pub fn has_key() -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| KEY_QUEUE.lock().len > 0)
+}
+
+/// A callback claimed for an IRQ line through `register`.
+pub type IrqHandler = fn();
+
+/// Callbacks claimed through `register`, indexed by IRQ line.
+static HANDLERS: Mutex<[Option<IrqHandler>; 16]> = Mutex::new([None; 16]);
+
+/// IRQ lines dispatched through `HANDLERS`, with their IDT entries.
+/// IRQ0/1 keep their dedicated handlers and IRQ2 is the PIC cascade.
+pub(super) const IRQ_TRAMPOLINES: [(u8, HandlerFunc); 13] = [
+    (3, irq_trampoline::<3>),
+    (4, irq_trampoline::<4>),
+    (5, irq_trampoline::<5>),
+    (6, irq_trampoline::<6>),
+    (7, irq_trampoline::<7>),
+    (8, irq_trampoline::<8>),
+    (9, irq_trampoline::<9>),
+    (10, irq_trampoline::<10>),
+    (11, irq_trampoline::<11>),
+    (12, irq_trampoline::<12>),
+    (13, irq_trampoline::<13>),
+    (14, irq_trampoline::<14>),
+    (15, irq_trampoline::<15>),
+];
+
+/// Claim interrupt `vector` for `handler`, e.g. `PIC_1_OFFSET + 4` for
+/// IRQ4 (COM1). Returns false if the vector isn't one of the
+/// `IRQ_TRAMPOLINES` lines or is already claimed.
+///
+/// `handler` runs in interrupt context with interrupts disabled, and the
+/// PIC is sent EOI once it returns.
+pub fn register(vector: u8, handler: IrqHandler) -> bool {
+    let Some(irq) = vector.checked_sub(PIC_1_OFFSET) else {
+        return false;
+    };
+    if !IRQ_TRAMPOLINES.iter().any(|(line, _)| *line == irq) {
+        return false;
+    }
+    // The trampolines take this lock too, so hold it with interrupts off
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let slot = &mut handlers[usize::from(irq)];
+        if slot.is_some() {
+            return false;
+        }
+        *slot = Some(handler);
+        true
+    })
+}
+
 /// Initialize the 8259 PIC.
 pub fn init_pic() {
     unsafe {
@@ -62,7 +230,9 @@ pub extern "x86-interrupt" fn timer_handler(_stack_frame: InterruptStackFrame) {
     TICKS.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
 
     // Decrement scheduler fuel counter
-    crate::task::scheduler::timer_tick();
+    if !crate::task::scheduler::timer_tick() {
+        IDLE_TICKS.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+    }
 
     // Send EOI directly via port I/O to avoid locking PICS mutex
     unsafe {
@@ -76,31 +246,28 @@ pub extern "x86-interrupt" fn keyboard_handler(_stack_frame: InterruptStackFrame
 
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
+    feed_scancode(scancode);
 
-    // Use pc-keyboard crate to decode scancodes
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, ScancodeSet1};
-
-    lazy_static::lazy_static! {
-        static ref KEYBOARD: Mutex<pc_keyboard::Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(pc_keyboard::Keyboard::new(
-                ScancodeSet1::new(),
-                layouts::Us104Key,
-                HandleControl::Ignore,
-            ));
+    // Send EOI directly — PIC1 command port
+    unsafe {
+        x86_64::instructions::port::Port::<u8>::new(0x20).write(0x20);
     }
+}
 
-    let mut keyboard = KEYBOARD.lock();
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => crate::print!("{}", character),
-                DecodedKey::RawKey(key) => crate::print!("{:?}", key),
-            }
-        }
+/// Shared handler for the `IRQ_TRAMPOLINES` lines: runs the callback
+/// claimed for `IRQ`, if any, then sends EOI.
+extern "x86-interrupt" fn irq_trampoline<const IRQ: u8>(_stack_frame: InterruptStackFrame) {
+    // Copy the callback out so it runs without the table locked
+    let handler = HANDLERS.lock()[usize::from(IRQ)];
+    if let Some(handler) = handler {
+        handler();
     }
 
-    // Send EOI directly — PIC1 command port
+    // Lines on PIC2 need EOI on both chips
     unsafe {
+        if IRQ >= 8 {
+            x86_64::instructions::port::Port::<u8>::new(0xA0).write(0x20);
+        }
         x86_64::instructions::port::Port::<u8>::new(0x20).write(0x20);
     }
 }
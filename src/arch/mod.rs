@@ -1,8 +1,9 @@
 //! x86_64 architecture-specific initialization.
 
-mod gdt;
+pub mod gdt;
 mod idt;
 pub mod interrupts;
+pub mod rtc;
 
 /// Initialize CPU structures (GDT, IDT, PIC) and enable interrupts.
 pub fn init() {
@@ -11,3 +12,28 @@ pub fn init() {
     interrupts::init_pic();
     interrupts::enable();
 }
+
+/// Status reported to QEMU through the `isa-debug-exit` device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// I/O port of QEMU's `isa-debug-exit` device.
+const DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// Exit QEMU with `code`.
+///
+/// Needs QEMU started with
+/// `-device isa-debug-exit,iobase=0xf4,iosize=0x04`; QEMU then exits with
+/// status `(code << 1) | 1`, i.e. 33 for `Success` and 35 for `Failed`.
+/// Without the device the write is ignored and the CPU is halted instead.
+pub fn exit_qemu(code: ExitCode) -> ! {
+    use x86_64::instructions::port::Port;
+    unsafe {
+        Port::<u32>::new(DEBUG_EXIT_PORT).write(code as u32);
+    }
+    crate::halt_loop()
+}
@@ -0,0 +1,136 @@
+//! CMOS real-time clock.
+//!
+//! Reads wall-clock date and time from the RTC registers behind ports
+//! 0x70 (register select) and 0x71 (data). The century register isn't
+//! standardized, so years are assumed to be 20xx.
+
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+/// Status A: an update is in progress and the registers may be torn.
+const STATUS_A_UPDATING: u8 = 0x80;
+/// Status B: hours are in 24-hour format.
+const STATUS_B_24H: u8 = 0x02;
+/// Status B: values are binary rather than BCD.
+const STATUS_B_BINARY: u8 = 0x04;
+/// In 12-hour mode, set in the hours register for PM.
+const HOUR_PM: u8 = 0x80;
+
+/// A calendar date and time of day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl core::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Raw register values, before format conversion.
+#[derive(PartialEq, Eq)]
+struct Raw {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+}
+
+fn read_register(reg: u8) -> u8 {
+    let mut address = Port::<u8>::new(CMOS_ADDRESS);
+    let mut data = Port::<u8>::new(CMOS_DATA);
+    unsafe {
+        address.write(reg);
+        data.read()
+    }
+}
+
+fn read_raw() -> Raw {
+    while read_register(REG_STATUS_A) & STATUS_A_UPDATING != 0 {
+        core::hint::spin_loop();
+    }
+    Raw {
+        second: read_register(REG_SECONDS),
+        minute: read_register(REG_MINUTES),
+        hour: read_register(REG_HOURS),
+        day: read_register(REG_DAY),
+        month: read_register(REG_MONTH),
+        year: read_register(REG_YEAR),
+    }
+}
+
+/// Convert a packed BCD byte (e.g. `0x59`) to binary (`59`).
+pub fn bcd_to_binary(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0x0F)
+}
+
+/// Current date and time from the RTC.
+///
+/// Reads until two consecutive snapshots agree, so an update landing
+/// mid-read can't produce a torn value, then applies the BCD and
+/// 12-hour format bits from status register B.
+pub fn now() -> DateTime {
+    let (raw, status_b) = without_interrupts(|| {
+        let mut raw = read_raw();
+        loop {
+            let again = read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        (raw, read_register(REG_STATUS_B))
+    });
+    convert(raw, status_b)
+}
+
+fn convert(raw: Raw, status_b: u8) -> DateTime {
+    let decode = |v: u8| {
+        if status_b & STATUS_B_BINARY != 0 { v } else { bcd_to_binary(v) }
+    };
+
+    let pm = raw.hour & HOUR_PM != 0;
+    let mut hour = decode(raw.hour & !HOUR_PM);
+    if status_b & STATUS_B_24H == 0 {
+        // 12 AM is midnight, 12 PM is noon
+        hour = match (hour, pm) {
+            (12, false) => 0,
+            (12, true) => 12,
+            (h, true) => h + 12,
+            (h, false) => h,
+        };
+    }
+
+    DateTime {
+        year: 2000 + decode(raw.year) as u16,
+        month: decode(raw.month),
+        day: decode(raw.day),
+        hour,
+        minute: decode(raw.minute),
+        second: decode(raw.second),
+    }
+}
@@ -0,0 +1,85 @@
+//! Capability audit log — a fixed-size ring of recent manager operations.
+//!
+//! Every mint/verify/restrict/revoke is recorded with its result and the
+//! tick at which it happened, so failed access attempts are visible.
+//! When the ring is full the oldest entry is overwritten.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use super::{CapId, CapError};
+
+/// Number of entries retained in the ring.
+pub const AUDIT_CAPACITY: usize = 256;
+
+/// Kind of capability operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEvent {
+    Mint,
+    Verify,
+    Restrict,
+    Revoke,
+    CloneRef,
+    DropRef,
+}
+
+/// A single audit record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub cap_id: CapId,
+    pub event: AuditEvent,
+    pub result: Result<(), CapError>,
+    pub tick: u64,
+}
+
+impl core::fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "[t={}] {:?} {}", self.tick, self.event, self.cap_id)?;
+        match self.result {
+            Ok(()) => write!(f, " ok"),
+            Err(e) => write!(f, " ✗ {}", e),
+        }
+    }
+}
+
+/// Global audit ring.
+static AUDIT: Mutex<AuditRing> = Mutex::new(AuditRing::new());
+
+struct AuditRing {
+    entries: [Option<AuditEntry>; AUDIT_CAPACITY],
+    /// Index of the next slot to write.
+    head: usize,
+}
+
+impl AuditRing {
+    const fn new() -> Self {
+        Self { entries: [None; AUDIT_CAPACITY], head: 0 }
+    }
+}
+
+/// Record an operation in the audit log.
+pub fn record(cap_id: CapId, event: AuditEvent, result: Result<(), CapError>) {
+    let entry = AuditEntry {
+        cap_id,
+        event,
+        result,
+        tick: crate::arch::interrupts::ticks(),
+    };
+    let mut ring = AUDIT.lock();
+    let head = ring.head;
+    ring.entries[head] = Some(entry);
+    ring.head = (head + 1) % AUDIT_CAPACITY;
+}
+
+/// Snapshot of the log, oldest entry first.
+pub fn dump() -> Vec<AuditEntry> {
+    let ring = AUDIT.lock();
+    let (newer, older) = ring.entries.split_at(ring.head);
+    older.iter().chain(newer.iter()).flatten().copied().collect()
+}
+
+/// Discard all recorded entries.
+pub fn clear() {
+    let mut ring = AUDIT.lock();
+    ring.entries = [None; AUDIT_CAPACITY];
+    ring.head = 0;
+}
@@ -1,59 +1,409 @@
 //! Capability Manager — the kernel's authority for minting,
 //! verifying, restricting, and revoking capabilities.
 
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
 use spin::Mutex;
-use super::{CapId, Capability, CapError, Resource, Rights};
+use super::{CapId, CapInfo, CapStats, Capability, CapError, FastCap, GroupId, Resource, Rights};
+use super::audit::{self, AuditEvent};
+use super::seal;
+use crate::arch::interrupts::ticks;
+use crate::wire::{Reader, Writer};
 
 /// Global capability manager instance.
 static MANAGER: Mutex<CapManagerInner> = Mutex::new(CapManagerInner::new());
 
+/// Bumped whenever a capability is revoked, removed or replaced, so
+/// `FastCap` caches taken before the change stop matching.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Invalidate every `FastCap` cache. Called with the manager lock held,
+/// before the change becomes visible to `verify`.
+fn bump_generation() {
+    GENERATION.fetch_add(1, Ordering::Release);
+}
+
 struct CapManagerInner {
     caps: Option<BTreeMap<CapId, Capability>>,
+    /// Live (non-revoked) caps per resource. Revoked caps are pruned
+    /// eagerly, so lookups never have to filter.
+    by_resource: Option<BTreeMap<Resource, Vec<CapId>>>,
+    /// Names given by `mint_named`, dropped when their cap is removed.
+    names: Option<BTreeMap<String, CapId>>,
+    /// Members of each group minted by `mint_group`, in spec order.
+    groups: Option<BTreeMap<GroupId, Vec<CapId>>>,
 }
 
 impl CapManagerInner {
     const fn new() -> Self {
         // BTreeMap can't be const-constructed, so we use Option
-        Self { caps: None }
+        Self { caps: None, by_resource: None, names: None, groups: None }
     }
 
     fn caps(&mut self) -> &mut BTreeMap<CapId, Capability> {
         self.caps.get_or_insert_with(BTreeMap::new)
     }
+
+    fn by_resource(&mut self) -> &mut BTreeMap<Resource, Vec<CapId>> {
+        self.by_resource.get_or_insert_with(BTreeMap::new)
+    }
+
+    fn names(&mut self) -> &mut BTreeMap<String, CapId> {
+        self.names.get_or_insert_with(BTreeMap::new)
+    }
+
+    fn groups(&mut self) -> &mut BTreeMap<GroupId, Vec<CapId>> {
+        self.groups.get_or_insert_with(BTreeMap::new)
+    }
+
+    /// Insert a capability (replacing any with the same id) and index it.
+    fn add(&mut self, cap: Capability) {
+        let (id, resource, live) = (cap.id, cap.resource.clone(), !cap.revoked);
+        if let Some(old) = self.caps().insert(id, cap) {
+            bump_generation();
+            self.unindex(&old.resource, id);
+        }
+        if live {
+            self.by_resource().entry(resource).or_default().push(id);
+        }
+    }
+
+    /// Remove a capability entirely, dropping it from the index.
+    fn remove(&mut self, id: CapId) {
+        if let Some(cap) = self.caps().remove(&id) {
+            bump_generation();
+            self.unindex(&cap.resource, id);
+            self.names().retain(|_, named| *named != id);
+        }
+    }
+
+    /// Mark a capability revoked and drop it from the index.
+    fn mark_revoked(&mut self, id: CapId) -> Result<(), CapError> {
+        let cap = self.caps().get_mut(&id).ok_or(CapError::NotFound)?;
+        cap.revoked = true;
+        bump_generation();
+        let resource = cap.resource.clone();
+        self.unindex(&resource, id);
+        Ok(())
+    }
+
+    /// Remove `id` from a resource's index entry, pruning the entry once empty.
+    fn unindex(&mut self, resource: &Resource, id: CapId) {
+        if let Some(ids) = self.by_resource().get_mut(resource) {
+            ids.retain(|i| *i != id);
+            if ids.is_empty() {
+                self.by_resource().remove(resource);
+            }
+        }
+    }
 }
 
 /// Mint a new capability (kernel-only operation).
 pub fn mint(resource: Resource, rights: Rights, delegatable: bool) -> CapId {
-    let id = CapId::mint();
-    let cap = Capability {
-        id,
+    insert(root(resource, rights, delegatable))
+}
+
+/// Mint a capability that stops verifying once `ticks()` passes
+/// `expires_at_tick`. The deadline tick itself is still valid.
+pub fn mint_with_expiry(
+    resource: Resource,
+    rights: Rights,
+    delegatable: bool,
+    expires_at_tick: u64,
+) -> CapId {
+    let mut cap = root(resource, rights, delegatable);
+    cap.expires_at = Some(expires_at_tick);
+    insert(cap)
+}
+
+/// Mint a capability that verifies successfully at most `max_uses` times.
+///
+/// Caps restricted from it share the budget: a use of any of them is
+/// charged to it as well.
+pub fn mint_with_quota(
+    resource: Resource,
+    rights: Rights,
+    delegatable: bool,
+    max_uses: u64,
+) -> CapId {
+    let mut cap = root(resource, rights, delegatable);
+    cap.remaining_uses = Some(max_uses);
+    insert(cap)
+}
+
+/// Mint several capabilities under a single lock acquisition.
+///
+/// Each spec is `(resource, rights, delegatable)`; ids are returned in
+/// the same order.
+pub fn mint_batch(specs: &[(Resource, Rights, bool)]) -> Vec<CapId> {
+    let batch: Vec<Capability> = specs
+        .iter()
+        .map(|(resource, rights, delegatable)| root(resource.clone(), *rights, *delegatable))
+        .collect();
+    let ids: Vec<CapId> = batch.iter().map(|c| c.id).collect();
+
+    let mut mgr = MANAGER.lock();
+    for cap in batch {
+        mgr.add(cap);
+    }
+    drop(mgr);
+
+    for &id in &ids {
+        audit::record(id, AuditEvent::Mint, Ok(()));
+    }
+    ids
+}
+
+/// Mint several capabilities as one group, under a single lock
+/// acquisition, and return the group's handle.
+///
+/// Specs are as for `mint_batch`. The members are ordinary capabilities
+/// that can be verified, restricted or revoked individually; the group
+/// additionally lets them be listed with `group_caps` and revoked
+/// together with `revoke_group`.
+pub fn mint_group(specs: &[(Resource, Rights, bool)]) -> GroupId {
+    let group = GroupId::mint();
+    let batch: Vec<Capability> = specs
+        .iter()
+        .map(|(resource, rights, delegatable)| root(resource.clone(), *rights, *delegatable))
+        .collect();
+    let ids: Vec<CapId> = batch.iter().map(|c| c.id).collect();
+
+    let mut mgr = MANAGER.lock();
+    for cap in batch {
+        mgr.add(cap);
+    }
+    mgr.groups().insert(group, ids.clone());
+    drop(mgr);
+
+    for &id in &ids {
+        audit::record(id, AuditEvent::Mint, Ok(()));
+    }
+    group
+}
+
+/// The capabilities minted into `group`, in spec order. Members stay
+/// listed after being revoked, and drop out once removed (see `drop_ref`).
+pub fn group_caps(group: GroupId) -> Result<Vec<CapId>, CapError> {
+    let mgr = MANAGER.lock();
+    let members = mgr.groups.as_ref().and_then(|g| g.get(&group)).ok_or(CapError::NotFound)?;
+    let caps = mgr.caps.as_ref();
+    Ok(members.iter()
+        .copied()
+        .filter(|id| caps.is_some_and(|c| c.contains_key(id)))
+        .collect())
+}
+
+/// Revoke every member of `group` under a single lock acquisition, so no
+/// verification can see some members revoked and others not. Returns how
+/// many members were revoked; members already removed are skipped.
+/// Like `revoke`, children restricted from a member stay valid.
+pub fn revoke_group(group: GroupId) -> Result<usize, CapError> {
+    let mut mgr = MANAGER.lock();
+    let members = mgr.groups().get(&group).cloned().ok_or(CapError::NotFound)?;
+    let revoked: Vec<CapId> = members
+        .into_iter()
+        .filter(|&id| mgr.mark_revoked(id).is_ok())
+        .collect();
+    drop(mgr);
+
+    for &id in &revoked {
+        audit::record(id, AuditEvent::Revoke, Ok(()));
+    }
+    Ok(revoked.len())
+}
+
+/// A fresh top-level capability with a newly minted id.
+fn root(resource: Resource, rights: Rights, delegatable: bool) -> Capability {
+    Capability {
+        id: CapId::mint(),
         resource,
         rights,
         delegatable,
         revoked: false,
-    };
-    MANAGER.lock().caps().insert(id, cap);
+        expires_at: None,
+        parent: None,
+        refcount: 1,
+        remaining_uses: None,
+    }
+}
+
+/// Mint a root capability and register it under `name` for `lookup`.
+///
+/// Fails with `NameTaken` if `name` already refers to a capability. The
+/// name is released once the capability is removed (see `drop_ref`);
+/// revoking it keeps the name taken.
+pub fn mint_named(name: &str, resource: Resource, rights: Rights, delegatable: bool) -> Result<CapId, CapError> {
+    let mut mgr = MANAGER.lock();
+    if mgr.names().contains_key(name) {
+        return Err(CapError::NameTaken);
+    }
+    let cap = root(resource, rights, delegatable);
+    let id = cap.id;
+    mgr.add(cap);
+    mgr.names().insert(String::from(name), id);
+    drop(mgr);
+    audit::record(id, AuditEvent::Mint, Ok(()));
+    Ok(id)
+}
+
+/// The capability registered under `name` by `mint_named`, if any.
+pub fn lookup(name: &str) -> Option<CapId> {
+    MANAGER.lock().names.as_ref()?.get(name).copied()
+}
+
+fn insert(cap: Capability) -> CapId {
+    let id = cap.id;
+    MANAGER.lock().add(cap);
+    audit::record(id, AuditEvent::Mint, Ok(()));
     id
 }
 
+/// Whether following parent links from `start` ends at a root rather
+/// than revisiting a capability.
+fn chain_is_acyclic(start: CapId, parent_of: impl Fn(CapId) -> Option<CapId>) -> bool {
+    let mut visited = BTreeSet::new();
+    let mut next = Some(start);
+    while let Some(id) = next {
+        if !visited.insert(id) {
+            return false;
+        }
+        next = parent_of(id);
+    }
+    true
+}
+
+/// Record the outcome of an operation in the audit log and pass it through.
+fn audited<T>(cap_id: CapId, event: AuditEvent, result: Result<T, CapError>) -> Result<T, CapError> {
+    audit::record(cap_id, event, result.as_ref().map(|_| ()).map_err(|e| *e));
+    result
+}
+
+/// Whether a capability's deadline has passed at the current tick.
+fn is_expired(cap: &Capability) -> bool {
+    cap.expires_at.is_some_and(|deadline| ticks() > deadline)
+}
+
 /// Verify that a capability grants the required rights.
+///
+/// Both successful and failed checks are recorded in the audit log.
 pub fn verify(cap_id: CapId, required: Rights) -> Result<(), CapError> {
-    let mgr = MANAGER.lock();
-    let caps = mgr.caps.as_ref().ok_or(CapError::NotFound)?;
+    audited(cap_id, AuditEvent::Verify, check(cap_id, required))
+}
+
+/// Like `verify`, but skips the manager lock when `cap`'s cache is still
+/// valid.
+///
+/// A full `verify` fills the cache with the cap's rights; later calls
+/// that need no more than those rights succeed without locking as long as
+/// no capability has been revoked, removed or replaced since. Any such
+/// change invalidates every cache, so revocation takes effect on the next
+/// call. Caps with an expiry or a use quota are never cached, and cache
+/// hits are not recorded in the audit log.
+pub fn verify_cached(cap: &FastCap, required: Rights) -> Result<(), CapError> {
+    if let Some((rights, generation)) = cap.cached.get() {
+        if generation == GENERATION.load(Ordering::Acquire) && rights.contains(required) {
+            return Ok(());
+        }
+    }
+
+    // Read before verifying: a revoke in between leaves the cache stale
+    let generation = GENERATION.load(Ordering::Acquire);
+    verify(cap.id, required)?;
+    let mut mgr = MANAGER.lock();
+    let cacheable = mgr.caps().get(&cap.id)
+        .filter(|c| c.expires_at.is_none() && c.remaining_uses.is_none())
+        .map(|c| c.rights);
+    cap.cached.set(cacheable.map(|rights| (rights, generation)));
+    Ok(())
+}
+
+fn check(cap_id: CapId, required: Rights) -> Result<(), CapError> {
+    // Mutable: a successful check spends one use of a quota'd cap
+    let mut mgr = MANAGER.lock();
+    let caps = mgr.caps.as_mut().ok_or(CapError::NotFound)?;
     let cap = caps.get(&cap_id).ok_or(CapError::NotFound)?;
 
     if cap.revoked {
         return Err(CapError::Revoked);
     }
+    if is_expired(cap) {
+        return Err(CapError::Expired);
+    }
     if !cap.rights.contains(required) {
         return Err(CapError::PermissionDenied);
     }
+
+    // A use is charged to the cap and to every quota'd ancestor, so
+    // restricting a cap never mints extra uses
+    let mut charged = Vec::new();
+    let mut visited = BTreeSet::new();
+    let mut next = Some(cap_id);
+    while let Some(id) = next {
+        let Some(c) = caps.get(&id).filter(|_| visited.insert(id)) else {
+            break;
+        };
+        match c.remaining_uses {
+            Some(0) => return Err(CapError::QuotaExhausted),
+            Some(_) => charged.push(id),
+            None => {}
+        }
+        next = c.parent;
+    }
+    for id in charged {
+        if let Some(remaining) = caps.get_mut(&id).and_then(|c| c.remaining_uses.as_mut()) {
+            *remaining -= 1;
+        }
+    }
     Ok(())
 }
 
 /// Create a restricted child capability with ≤ rights.
 pub fn restrict(parent_id: CapId, new_rights: Rights) -> Result<CapId, CapError> {
+    derive(parent_id, new_rights, |parent| Ok(parent.clone()))
+}
+
+/// Create a child capability over a sub-range of a memory capability.
+///
+/// `[new_base, new_base + new_size)` must lie fully within the parent's
+/// region and `new_rights` must be a subset of the parent's rights.
+pub fn restrict_memory(
+    parent_id: CapId,
+    new_base: u64,
+    new_size: u64,
+    new_rights: Rights,
+) -> Result<CapId, CapError> {
+    derive(parent_id, new_rights, |parent| match *parent {
+        Resource::Memory { base, size } => {
+            let new_end = new_base.checked_add(new_size).ok_or(CapError::CannotEscalate)?;
+            let end = base.checked_add(size).ok_or(CapError::CannotEscalate)?;
+            if new_base < base || new_end > end {
+                return Err(CapError::CannotEscalate);
+            }
+            Ok(Resource::Memory { base: new_base, size: new_size })
+        }
+        _ => Err(CapError::CannotEscalate),
+    })
+}
+
+/// Shared delegation path: validate the parent, then insert a child
+/// with `new_rights` over the resource produced by `narrow`.
+fn derive(
+    parent_id: CapId,
+    new_rights: Rights,
+    narrow: impl FnOnce(&Resource) -> Result<Resource, CapError>,
+) -> Result<CapId, CapError> {
+    audited(parent_id, AuditEvent::Restrict, derive_child(parent_id, new_rights, narrow))
+}
+
+fn derive_child(
+    parent_id: CapId,
+    new_rights: Rights,
+    narrow: impl FnOnce(&Resource) -> Result<Resource, CapError>,
+) -> Result<CapId, CapError> {
     let mut mgr = MANAGER.lock();
     let caps = mgr.caps.as_ref().ok_or(CapError::NotFound)?;
     let parent = caps.get(&parent_id).ok_or(CapError::NotFound)?;
@@ -61,6 +411,9 @@ pub fn restrict(parent_id: CapId, new_rights: Rights) -> Result<CapId, CapError>
     if parent.revoked {
         return Err(CapError::Revoked);
     }
+    if is_expired(parent) {
+        return Err(CapError::Expired);
+    }
     if !parent.delegatable {
         return Err(CapError::NotDelegatable);
     }
@@ -68,30 +421,310 @@ pub fn restrict(parent_id: CapId, new_rights: Rights) -> Result<CapId, CapError>
     if !parent.rights.contains(new_rights) {
         return Err(CapError::CannotEscalate);
     }
+    // A corrupted (e.g. imported) table could loop; never extend a cycle
+    if !chain_is_acyclic(parent_id, |id| caps.get(&id).and_then(|c| c.parent)) {
+        return Err(CapError::Cycle);
+    }
+    let resource = narrow(&parent.resource)?;
 
     let child_id = CapId::mint();
     let child = Capability {
         id: child_id,
-        resource: parent.resource.clone(),
+        resource,
         rights: new_rights,
         delegatable: parent.delegatable,
         revoked: false,
+        // A child can never outlive its parent
+        expires_at: parent.expires_at,
+        parent: Some(parent_id),
+        refcount: 1,
+        // Starts from what's left; `check` also charges every use to the
+        // parent, so the two draw on one budget
+        remaining_uses: parent.remaining_uses,
     };
 
     // Need mutable access to insert
-    mgr.caps().insert(child_id, child);
+    mgr.add(child);
     Ok(child_id)
 }
 
 /// Revoke a capability (marks it invalid, O(1)).
+///
+/// Children restricted from it stay valid; use `revoke_tree` to
+/// cascade through the delegation tree.
 pub fn revoke(cap_id: CapId) -> Result<(), CapError> {
+    audited(cap_id, AuditEvent::Revoke, revoke_one(cap_id))
+}
+
+fn revoke_one(cap_id: CapId) -> Result<(), CapError> {
+    MANAGER.lock().mark_revoked(cap_id)
+}
+
+/// Revoke every capability pointing at `resource`, e.g. when a device is
+/// removed or an object deleted. Resources match by value, so `Memory`
+/// requires an exact base/size match. Returns how many caps were newly
+/// marked revoked; already-revoked caps are not counted.
+pub fn revoke_for_resource(resource: &Resource) -> usize {
+    let mut mgr = MANAGER.lock();
+    let revoked = mgr.by_resource().remove(resource).unwrap_or_default();
+    if !revoked.is_empty() {
+        bump_generation();
+    }
+    for id in &revoked {
+        if let Some(cap) = mgr.caps().get_mut(id) {
+            cap.revoked = true;
+        }
+    }
+    drop(mgr);
+    for id in &revoked {
+        audit::record(*id, AuditEvent::Revoke, Ok(()));
+    }
+    revoked.len()
+}
+
+/// Take another reference to a capability so it can be shared.
+///
+/// Returns the same id; each holder must eventually call `drop_ref`.
+pub fn clone_ref(cap_id: CapId) -> Result<CapId, CapError> {
+    audited(cap_id, AuditEvent::CloneRef, take_ref(cap_id))
+}
+
+fn take_ref(cap_id: CapId) -> Result<CapId, CapError> {
     let mut mgr = MANAGER.lock();
     let caps = mgr.caps.as_mut().ok_or(CapError::NotFound)?;
     let cap = caps.get_mut(&cap_id).ok_or(CapError::NotFound)?;
-    cap.revoked = true;
+    if cap.revoked {
+        return Err(CapError::Revoked);
+    }
+    cap.refcount = cap.refcount.saturating_add(1);
+    Ok(cap_id)
+}
+
+/// Release a reference. When the last reference is dropped the entry is
+/// removed from the manager entirely; further drops return `NotFound`.
+///
+/// Revocation is independent of the refcount: `revoke` takes effect
+/// immediately for every holder.
+pub fn drop_ref(cap_id: CapId) -> Result<(), CapError> {
+    audited(cap_id, AuditEvent::DropRef, release_ref(cap_id))
+}
+
+fn release_ref(cap_id: CapId) -> Result<(), CapError> {
+    let mut mgr = MANAGER.lock();
+    let caps = mgr.caps.as_mut().ok_or(CapError::NotFound)?;
+    let cap = caps.get_mut(&cap_id).ok_or(CapError::NotFound)?;
+    // A zero refcount can't come from the manager itself, but don't wrap
+    cap.refcount = cap.refcount.checked_sub(1).ok_or(CapError::Corrupt)?;
+    if cap.refcount == 0 {
+        mgr.remove(cap_id);
+    }
     Ok(())
 }
 
+/// Revoke a capability and every capability transitively restricted
+/// from it. Returns the number of capabilities revoked.
+pub fn revoke_tree(cap_id: CapId) -> Result<usize, CapError> {
+    audited(cap_id, AuditEvent::Revoke, revoke_descendants(cap_id))
+}
+
+fn revoke_descendants(cap_id: CapId) -> Result<usize, CapError> {
+    let mut mgr = MANAGER.lock();
+    if !mgr.caps().contains_key(&cap_id) {
+        return Err(CapError::NotFound);
+    }
+
+    let mut pending = vec![cap_id];
+    // Parent links should form a tree, but don't loop forever if they don't
+    let mut visited = BTreeSet::new();
+    let mut revoked = 0;
+    while let Some(id) = pending.pop() {
+        if !visited.insert(id) {
+            continue;
+        }
+        if mgr.mark_revoked(id).is_ok() {
+            revoked += 1;
+        }
+        pending.extend(
+            mgr.caps().values()
+                .filter(|c| c.parent == Some(id))
+                .map(|c| c.id),
+        );
+    }
+    Ok(revoked)
+}
+
+/// Whether capability `a` can stand in for capability `b`: `a`'s resource
+/// covers `b`'s (see `Resource::covers`), `a`'s rights are a superset of
+/// `b`'s, and neither is revoked or expired.
+pub fn dominates(a: CapId, b: CapId) -> Result<bool, CapError> {
+    let mgr = MANAGER.lock();
+    let caps = mgr.caps.as_ref().ok_or(CapError::NotFound)?;
+    let cap_a = caps.get(&a).ok_or(CapError::NotFound)?;
+    let cap_b = caps.get(&b).ok_or(CapError::NotFound)?;
+
+    let live = |c: &Capability| !c.revoked && !is_expired(c);
+    Ok(live(cap_a)
+        && live(cap_b)
+        && cap_a.rights.contains(cap_b.rights)
+        && cap_a.resource.covers(&cap_b.resource))
+}
+
+/// The rights granted by holding both `a` and `b` together, i.e. the
+/// intersection of their rights.
+///
+/// Both must be over the same resource (compared by value), or this fails
+/// with `ResourceMismatch`. A revoked or expired cap fails with `Revoked`
+/// or `Expired`.
+pub fn combine(a: CapId, b: CapId) -> Result<Rights, CapError> {
+    let mgr = MANAGER.lock();
+    let caps = mgr.caps.as_ref().ok_or(CapError::NotFound)?;
+    let cap_a = caps.get(&a).ok_or(CapError::NotFound)?;
+    let cap_b = caps.get(&b).ok_or(CapError::NotFound)?;
+
+    for cap in [cap_a, cap_b] {
+        if cap.revoked {
+            return Err(CapError::Revoked);
+        }
+        if is_expired(cap) {
+            return Err(CapError::Expired);
+        }
+    }
+    if cap_a.resource != cap_b.resource {
+        return Err(CapError::ResourceMismatch);
+    }
+    Ok(cap_a.rights & cap_b.rights)
+}
+
+/// Everything a holder of `caps` may do to `resource`: the union of the
+/// rights of each cap whose resource covers it (see `Resource::covers`).
+///
+/// Unknown, revoked and expired caps, and caps over other resources,
+/// contribute nothing. Doesn't spend use quotas or touch the audit log.
+pub fn effective_rights(caps: &[CapId], resource: &Resource) -> Rights {
+    let mgr = MANAGER.lock();
+    let Some(table) = mgr.caps.as_ref() else {
+        return Rights::empty();
+    };
+    caps.iter()
+        .filter_map(|id| table.get(id))
+        .filter(|c| !c.revoked && !is_expired(c) && c.resource.covers(resource))
+        .fold(Rights::empty(), |rights, c| rights | c.rights)
+}
+
+/// Seal a capability id into an opaque handle for passing through
+/// untrusted code. Handles can't be derived from ids without the
+/// kernel's boot-time secret.
+pub fn seal(cap_id: CapId) -> u64 {
+    seal::encode(cap_id.0)
+}
+
+/// Recover the capability behind a sealed handle. Forged or tampered
+/// handles, and handles to caps that no longer exist, yield `NotFound`.
+pub fn unseal(handle: u64) -> Result<CapId, CapError> {
+    let id = CapId(seal::decode(handle));
+    let mgr = MANAGER.lock();
+    let caps = mgr.caps.as_ref().ok_or(CapError::NotFound)?;
+    if caps.contains_key(&id) {
+        Ok(id)
+    } else {
+        Err(CapError::NotFound)
+    }
+}
+
+/// Serialize the whole capability table, revoked entries included.
+///
+/// Layout (little-endian): entry count `u32`, then per capability its id
+/// `u64`, resource (tag byte plus fields), rights `u32`, delegatable and
+/// revoked bytes, expiry, parent and quota (each a presence byte plus
+/// `u64`), and refcount `u32`.
+pub fn export() -> Vec<u8> {
+    let mgr = MANAGER.lock();
+    let caps: Vec<&Capability> = mgr.caps.as_ref().map_or_else(Vec::new, |c| c.values().collect());
+
+    let mut w = Writer::new();
+    w.u32(caps.len() as u32);
+    for cap in caps {
+        w.u64(cap.id.0);
+        cap.resource.encode(&mut w);
+        w.u32(cap.rights.bits());
+        w.u8(cap.delegatable as u8);
+        w.u8(cap.revoked as u8);
+        for field in [cap.expires_at, cap.parent.map(|p| p.0), cap.remaining_uses] {
+            w.u8(field.is_some() as u8);
+            w.u64(field.unwrap_or(0));
+        }
+        w.u32(cap.refcount);
+    }
+    w.finish()
+}
+
+/// Load a table produced by `export`. Returns how many capabilities were
+/// loaded.
+///
+/// The buffer is fully decoded first; truncated or malformed input,
+/// including a zero refcount or parent links that would form a cycle,
+/// fails with `Corrupt`
+/// and changes nothing. Imported entries replace any live
+/// entry with the same id. The id counter is advanced past the highest
+/// imported id, so later mints can never alias an imported capability.
+pub fn import(buf: &[u8]) -> Result<usize, CapError> {
+    let caps = decode(&mut Reader::new(buf)).ok_or(CapError::Corrupt)?;
+    let count = caps.len();
+    let mut mgr = MANAGER.lock();
+
+    // Reject parent cycles, following imported entries before live ones
+    let imported: BTreeMap<CapId, Option<CapId>> = caps.iter().map(|c| (c.id, c.parent)).collect();
+    let live = mgr.caps();
+    let parent_of = |id: CapId| match imported.get(&id) {
+        Some(parent) => *parent,
+        None => live.get(&id).and_then(|c| c.parent),
+    };
+    if !caps.iter().all(|c| chain_is_acyclic(c.id, parent_of)) {
+        return Err(CapError::Corrupt);
+    }
+
+    if let Some(max) = caps.iter().map(|c| c.id).max() {
+        CapId::reserve_through(max);
+    }
+    for cap in caps {
+        mgr.add(cap);
+    }
+    Ok(count)
+}
+
+fn decode(r: &mut Reader) -> Option<Vec<Capability>> {
+    let count = r.u32()?;
+    let mut caps = Vec::new();
+    for _ in 0..count {
+        let id = CapId(r.u64()?);
+        let resource = Resource::decode(r)?;
+        let rights = Rights::from_bits(r.u32()?)?;
+        let delegatable = r.u8()? != 0;
+        let revoked = r.u8()? != 0;
+        let mut optional = || -> Option<Option<u64>> {
+            let present = r.u8()? != 0;
+            let value = r.u64()?;
+            Some(present.then_some(value))
+        };
+        let expires_at = optional()?;
+        let parent = optional()?.map(CapId);
+        let remaining_uses = optional()?;
+        caps.push(Capability {
+            id,
+            resource,
+            rights,
+            delegatable,
+            revoked,
+            expires_at,
+            parent,
+            // Every stored cap has at least one holder
+            refcount: r.u32().filter(|&n| n > 0)?,
+            remaining_uses,
+        });
+    }
+    r.is_empty().then_some(caps)
+}
+
 /// Get a description of a capability (for logging).
 pub fn describe(cap_id: CapId) -> Result<(Resource, Rights), CapError> {
     let mgr = MANAGER.lock();
@@ -99,3 +732,53 @@ pub fn describe(cap_id: CapId) -> Result<(Resource, Rights), CapError> {
     let cap = caps.get(&cap_id).ok_or(CapError::NotFound)?;
     Ok((cap.resource.clone(), cap.rights))
 }
+
+/// Get the full state of a capability, revoked or not, without
+/// exposing the manager's internal `Capability`.
+pub fn describe_full(cap_id: CapId) -> Result<CapInfo, CapError> {
+    let mgr = MANAGER.lock();
+    let caps = mgr.caps.as_ref().ok_or(CapError::NotFound)?;
+    caps.get(&cap_id).map(CapInfo::from).ok_or(CapError::NotFound)
+}
+
+/// List every live (non-revoked) capability pointing at `resource`.
+///
+/// Resources match by value, so `Memory` requires an exact base/size match.
+pub fn list_for_resource(resource: &Resource) -> Vec<CapId> {
+    let mgr = MANAGER.lock();
+    mgr.by_resource.as_ref()
+        .and_then(|idx| idx.get(resource))
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Dump every capability in the manager, including revoked ones.
+pub fn list_all() -> Vec<(CapId, Resource, Rights)> {
+    let mgr = MANAGER.lock();
+    mgr.caps.as_ref().map_or_else(Vec::new, |caps| {
+        caps.values()
+            .map(|c| (c.id, c.resource.clone(), c.rights))
+            .collect()
+    })
+}
+
+/// Count the capabilities in the manager under a single lock.
+///
+/// Revoked caps stay in the table until their last reference is
+/// dropped, so they count towards `total` and `revoked`.
+pub fn stats() -> CapStats {
+    let mgr = MANAGER.lock();
+    let mut stats = CapStats::default();
+    for cap in mgr.caps.iter().flat_map(|caps| caps.values()) {
+        stats.total += 1;
+        if cap.revoked {
+            stats.revoked += 1;
+        } else {
+            stats.live += 1;
+        }
+        if cap.delegatable {
+            stats.delegatable += 1;
+        }
+    }
+    stats
+}
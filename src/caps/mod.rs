@@ -3,9 +3,17 @@
 //! Zero ambient authority: tasks start with no rights and must be
 //! explicitly granted capabilities to access any resource.
 
+pub mod audit;
 pub mod manager;
+mod seal;
 
 use core::sync::atomic::{AtomicU64, Ordering};
+use crate::wire::{Reader, Writer};
+
+/// Initialize the capability system (draws the sealing secret).
+pub fn init() {
+    seal::init();
+}
 
 // ─── Core types ──────────────────────────────────────────────────
 
@@ -17,14 +25,51 @@ static NEXT_CAP_ID: AtomicU64 = AtomicU64::new(1);
 
 impl CapId {
     /// Mint a new unique capability ID (kernel-only).
+    ///
+    /// IDs are never reused: a wrapped counter would let a stale handle
+    /// alias a fresh capability, so running out of IDs is a deliberate
+    /// panic rather than a silent wrap. The counter stops at `u64::MAX`
+    /// and every later mint panics too.
     fn mint() -> Self {
-        CapId(NEXT_CAP_ID.fetch_add(1, Ordering::Relaxed))
+        Self::try_mint().expect("capability ID space exhausted; refusing to reuse IDs")
+    }
+
+    /// `mint`, but returns None instead of panicking once the ID space
+    /// is exhausted.
+    pub(crate) fn try_mint() -> Option<Self> {
+        let mut id = NEXT_CAP_ID.load(Ordering::Relaxed);
+        loop {
+            let next = id.checked_add(1)?;
+            match NEXT_CAP_ID.compare_exchange_weak(id, next, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return Some(CapId(id)),
+                Err(current) => id = current,
+            }
+        }
+    }
+
+    /// Point the ID counter at `next`, returning the old value, so the
+    /// selftest can reach the end of the ID space.
+    #[cfg(feature = "qemu-test")]
+    pub fn set_next_for_test(next: u64) -> u64 {
+        NEXT_CAP_ID.swap(next, Ordering::Relaxed)
     }
 
     /// Get the raw numeric ID (for display).
     pub fn raw(&self) -> u64 {
         self.0
     }
+
+    /// Rebuild an ID from `raw()`, e.g. one passed across the syscall ABI.
+    /// The result need not name a live capability.
+    pub fn from_raw(raw: u64) -> Self {
+        CapId(raw)
+    }
+
+    /// Make sure future mints hand out ids above `id`, e.g. after
+    /// importing a saved capability table.
+    fn reserve_through(id: CapId) {
+        NEXT_CAP_ID.fetch_max(id.0.saturating_add(1), Ordering::Relaxed);
+    }
 }
 
 impl core::fmt::Display for CapId {
@@ -33,6 +78,30 @@ impl core::fmt::Display for CapId {
     }
 }
 
+/// Handle for a set of capabilities minted together by
+/// `manager::mint_group`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GroupId(u64);
+
+static NEXT_GROUP_ID: AtomicU64 = AtomicU64::new(1);
+
+impl GroupId {
+    fn mint() -> Self {
+        GroupId(NEXT_GROUP_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Get the raw numeric ID (for display).
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+}
+
+impl core::fmt::Display for GroupId {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Group#{}", self.0)
+    }
+}
+
 // ─── Rights (bitflags) ──────────────────────────────────────────
 
 bitflags::bitflags! {
@@ -43,10 +112,12 @@ bitflags::bitflags! {
         const WRITE   = 0b0000_0010;
         const EXECUTE = 0b0000_0100;
         const DELETE  = 0b0000_1000;
+        const APPEND  = 0b0001_0000;
 
         const RW  = Self::READ.bits() | Self::WRITE.bits();
         const ALL = Self::READ.bits() | Self::WRITE.bits()
-                  | Self::EXECUTE.bits() | Self::DELETE.bits();
+                  | Self::EXECUTE.bits() | Self::DELETE.bits()
+                  | Self::APPEND.bits();
     }
 }
 
@@ -57,6 +128,7 @@ impl core::fmt::Display for Rights {
         if self.contains(Rights::WRITE)   { parts.push("W"); }
         if self.contains(Rights::EXECUTE) { parts.push("X"); }
         if self.contains(Rights::DELETE)  { parts.push("D"); }
+        if self.contains(Rights::APPEND)  { parts.push("A"); }
         if parts.is_empty() {
             write!(f, "NONE")
         } else {
@@ -68,7 +140,10 @@ impl core::fmt::Display for Rights {
 // ─── Resource ───────────────────────────────────────────────────
 
 /// The type of resource a capability grants access to.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// `Ord` and `Hash` follow structural equality, so resources can key
+/// per-resource indices.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Resource {
     /// A region of physical memory.
     Memory { base: u64, size: u64 },
@@ -80,6 +155,88 @@ pub enum Resource {
     Cpu(u64),
 }
 
+/// Which variant a `Resource` is, without its fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResourceKind {
+    Memory,
+    Device,
+    Object,
+    Cpu,
+}
+
+impl Resource {
+    /// The resource's variant, for coarse filtering.
+    pub fn kind(&self) -> ResourceKind {
+        match self {
+            Resource::Memory { .. } => ResourceKind::Memory,
+            Resource::Device(_) => ResourceKind::Device,
+            Resource::Object(_) => ResourceKind::Object,
+            Resource::Cpu(_) => ResourceKind::Cpu,
+        }
+    }
+
+    /// Whether access to `self` implies access to `other`.
+    ///
+    /// - `Memory`: `self`'s region contains `other`'s region.
+    /// - `Device`, `Object`: same id.
+    /// - `Cpu`: `self` grants at least as many ticks.
+    ///
+    /// Different variants never cover each other.
+    pub fn covers(&self, other: &Resource) -> bool {
+        match (self, other) {
+            (
+                Resource::Memory { base, size },
+                Resource::Memory { base: other_base, size: other_size },
+            ) => {
+                let (end, other_end) = (
+                    base.saturating_add(*size),
+                    other_base.saturating_add(*other_size),
+                );
+                other_base >= base && other_end <= end
+            }
+            (Resource::Device(a), Resource::Device(b)) => a == b,
+            (Resource::Object(a), Resource::Object(b)) => a == b,
+            (Resource::Cpu(a), Resource::Cpu(b)) => a >= b,
+            _ => false,
+        }
+    }
+}
+
+impl Resource {
+    /// Stable wire encoding: a variant tag byte, then the fields.
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Resource::Memory { base, size } => {
+                w.u8(0);
+                w.u64(*base);
+                w.u64(*size);
+            }
+            Resource::Device(id) => {
+                w.u8(1);
+                w.u32(*id);
+            }
+            Resource::Object(id) => {
+                w.u8(2);
+                w.u64(*id);
+            }
+            Resource::Cpu(ticks) => {
+                w.u8(3);
+                w.u64(*ticks);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Option<Resource> {
+        Some(match r.u8()? {
+            0 => Resource::Memory { base: r.u64()?, size: r.u64()? },
+            1 => Resource::Device(r.u32()?),
+            2 => Resource::Object(r.u64()?),
+            3 => Resource::Cpu(r.u64()?),
+            _ => return None,
+        })
+    }
+}
+
 impl core::fmt::Display for Resource {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
@@ -101,6 +258,75 @@ pub struct Capability {
     pub rights: Rights,
     pub delegatable: bool,
     pub revoked: bool,
+    /// Absolute tick after which the capability is no longer valid.
+    pub expires_at: Option<u64>,
+    /// The capability this one was restricted from, if any.
+    pub parent: Option<CapId>,
+    /// Number of outstanding holders; the entry is removed at zero.
+    pub refcount: u32,
+    /// Successful verifications left before the cap is exhausted.
+    pub remaining_uses: Option<u64>,
+}
+
+/// Public snapshot of a capability's state, returned by
+/// `manager::describe_full`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapInfo {
+    pub resource: Resource,
+    pub rights: Rights,
+    pub delegatable: bool,
+    pub revoked: bool,
+    pub expires_at: Option<u64>,
+    pub remaining_uses: Option<u64>,
+    pub parent: Option<CapId>,
+    pub refcount: u32,
+}
+
+impl From<&Capability> for CapInfo {
+    fn from(cap: &Capability) -> Self {
+        CapInfo {
+            resource: cap.resource.clone(),
+            rights: cap.rights,
+            delegatable: cap.delegatable,
+            revoked: cap.revoked,
+            expires_at: cap.expires_at,
+            remaining_uses: cap.remaining_uses,
+            parent: cap.parent,
+            refcount: cap.refcount,
+        }
+    }
+}
+
+/// Counts over the whole capability table, returned by `manager::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CapStats {
+    /// Every capability still in the table, revoked or not.
+    pub total: usize,
+    pub live: usize,
+    pub revoked: usize,
+    /// Capabilities with the delegatable flag, revoked or not.
+    pub delegatable: usize,
+}
+
+/// A capability handle with its last successful check cached, for hot
+/// loops; see `manager::verify_cached`. Meant to be owned by one task.
+#[derive(Debug, Clone)]
+pub struct FastCap {
+    id: CapId,
+    /// Rights seen and the manager generation they were seen at.
+    cached: core::cell::Cell<Option<(Rights, u64)>>,
+}
+
+impl FastCap {
+    /// Wrap `id` with an empty cache.
+    pub fn new(id: CapId) -> Self {
+        FastCap { id, cached: core::cell::Cell::new(None) }
+    }
+
+    /// The wrapped capability.
+    pub fn id(&self) -> CapId {
+        self.id
+    }
 }
 
 // ─── Errors ─────────────────────────────────────────────────────
@@ -118,6 +344,18 @@ pub enum CapError {
     CannotEscalate,
     /// Capability is not delegatable.
     NotDelegatable,
+    /// Capability's expiry tick has passed.
+    Expired,
+    /// Capability's use quota has been spent.
+    QuotaExhausted,
+    /// An import buffer was truncated or malformed.
+    Corrupt,
+    /// The parent chain loops back on itself.
+    Cycle,
+    /// `manager::mint_named` was given a name already in use.
+    NameTaken,
+    /// `manager::combine` was given caps over different resources.
+    ResourceMismatch,
 }
 
 impl core::fmt::Display for CapError {
@@ -128,6 +366,12 @@ impl core::fmt::Display for CapError {
             CapError::PermissionDenied => write!(f, "permission denied"),
             CapError::CannotEscalate => write!(f, "cannot escalate"),
             CapError::NotDelegatable => write!(f, "not delegatable"),
+            CapError::Expired => write!(f, "expired"),
+            CapError::QuotaExhausted => write!(f, "quota exhausted"),
+            CapError::Corrupt => write!(f, "corrupt data"),
+            CapError::Cycle => write!(f, "cyclic delegation chain"),
+            CapError::NameTaken => write!(f, "name already taken"),
+            CapError::ResourceMismatch => write!(f, "resource mismatch"),
         }
     }
 }
@@ -0,0 +1,53 @@
+//! Sealed capability handles.
+//!
+//! A handle is the `CapId` passed through a keyed 64-bit permutation (a
+//! four-round Feistel network with SipHash as the round function). The
+//! key is derived from the TSC the first time a handle is sealed, so
+//! handles cannot be predicted from ids and a forged handle decodes to an
+//! id that, with overwhelming probability, does not exist.
+
+use lazy_static::lazy_static;
+use crate::objstore::siphash::siphash;
+
+const ROUNDS: u32 = 4;
+
+lazy_static! {
+    /// Boot-time secret for the permutation.
+    static ref SECRET: (u64, u64) = {
+        let t0 = unsafe { core::arch::x86_64::_rdtsc() };
+        let t1 = unsafe { core::arch::x86_64::_rdtsc() };
+        let mix = |x: u64| siphash::<2, 4>(x, x.rotate_left(29), &t0.to_le_bytes());
+        (mix(t0), mix(t1 ^ 0x5ea1_ca95_5ea1_ca95))
+    };
+}
+
+/// Force the secret to be drawn now rather than on first use.
+pub fn init() {
+    lazy_static::initialize(&SECRET);
+}
+
+fn round_fn(round: u32, half: u32) -> u32 {
+    let (k0, k1) = *SECRET;
+    let mut block = [0u8; 8];
+    block[..4].copy_from_slice(&round.to_le_bytes());
+    block[4..].copy_from_slice(&half.to_le_bytes());
+    siphash::<1, 3>(k0, k1, &block) as u32
+}
+
+/// Map a raw id to its sealed handle.
+pub fn encode(raw: u64) -> u64 {
+    let (mut left, mut right) = ((raw >> 32) as u32, raw as u32);
+    for round in 0..ROUNDS {
+        (left, right) = (right, left ^ round_fn(round, right));
+    }
+    ((left as u64) << 32) | right as u64
+}
+
+/// Inverse of `encode`.
+pub fn decode(handle: u64) -> u64 {
+    let (mut left, mut right) = ((handle >> 32) as u32, handle as u32);
+    for round in (0..ROUNDS).rev() {
+        (left, right) = (right ^ round_fn(round, left), left);
+    }
+    ((left as u64) << 32) | right as u64
+}
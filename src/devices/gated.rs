@@ -0,0 +1,29 @@
+//! Capability-gated port I/O.
+//!
+//! A cap over `Resource::Device(port)` authorizes exactly that I/O port:
+//! READ to `port_in`, WRITE to `port_out`.
+
+use x86_64::instructions::port::Port;
+use crate::caps::{self, CapError, CapId, Resource, Rights};
+
+/// Check that `cap_id` is a device cap for `port` granting `required`.
+fn authorize(cap_id: CapId, port: u16, required: Rights) -> Result<(), CapError> {
+    match caps::manager::describe(cap_id)? {
+        (Resource::Device(dev), _) if dev == u32::from(port) => {}
+        _ => return Err(CapError::PermissionDenied),
+    }
+    caps::manager::verify(cap_id, required)
+}
+
+/// Read a byte from I/O port `port` (requires READ cap on that port).
+pub fn port_in(cap_id: CapId, port: u16) -> Result<u8, CapError> {
+    authorize(cap_id, port, Rights::READ)?;
+    Ok(unsafe { Port::<u8>::new(port).read() })
+}
+
+/// Write a byte to I/O port `port` (requires WRITE cap on that port).
+pub fn port_out(cap_id: CapId, port: u16, val: u8) -> Result<(), CapError> {
+    authorize(cap_id, port, Rights::WRITE)?;
+    unsafe { Port::<u8>::new(port).write(val) };
+    Ok(())
+}
@@ -0,0 +1,6 @@
+//! Device access — hardware I/O behind `Resource::Device` capabilities.
+//!
+//! Library OS drivers go through `gated` rather than issuing port I/O
+//! themselves, so touching hardware always takes explicit authority.
+
+pub mod gated;
@@ -12,22 +12,28 @@
 #![no_std]
 #![no_main]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 
 extern crate alloc;
 
 mod arch;
 mod caps;
+mod devices;
+mod log;
 mod memory;
 mod objstore;
 mod serial;
+mod syscall;
 mod task;
+mod wire;
 
 use alloc::vec;
 use bootloader_api::config::Mapping;
 use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
-use caps::{Rights, Resource};
+use caps::{CapId, Rights, Resource};
 use caps::manager as cap_mgr;
 use objstore::{Object, gated as obj};
+use task::scheduler::Scheduler;
 use core::panic::PanicInfo;
 
 /// Configure bootloader to map all physical memory.
@@ -54,12 +60,32 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     arch::init();
     println!("[OK] GDT, IDT, TSS, PIC initialized");
     println!("[OK] Interrupts enabled");
+    println!("[OK] RTC: {}", arch::rtc::now());
     println!();
 
+    // Overflow the kernel stack; the double fault must land on its IST stack
+    #[cfg(feature = "stack-overflow-demo")]
+    {
+        println!("=== Stack Overflow Demo ===");
+        stack_overflow(0);
+    }
+
     // Initialize memory subsystem
     memory::init(boot_info);
+    // Nothing below reads BootInfo, so its regions can join the pool
+    unsafe { memory::reclaim_bootloader_regions() };
+
+    // Underrun the heap; the page fault handler must name the guard page
+    #[cfg(feature = "guard-page-demo")]
+    {
+        println!("=== Guard Page Demo ===");
+        let guard = (memory::heap::HEAP_START - 1) as *mut u8;
+        unsafe { core::ptr::write_volatile(guard, 0) };
+    }
     println!();
 
+    caps::init();
+
     // ── Capability System ─────────────────────────────────────
     println!("=== Capability System ===");
     println!();
@@ -143,6 +169,19 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
         }
         Err(e) => println!("[QUERY] failed: {}", e),
     }
+
+    match obj::query_by_meta(r_cap, "lang", "es") {
+        Ok(ids) => {
+            println!("[QUERY] meta:lang=es    → {} results", ids.len());
+            for id in &ids {
+                if let Ok(o) = obj::read(r_cap, *id) {
+                    let text = core::str::from_utf8(&o.content).unwrap_or("(bin)");
+                    println!("  {} → \"{}\"", id, text);
+                }
+            }
+        }
+        Err(e) => println!("[QUERY] failed: {}", e),
+    }
     println!();
 
     // ── Access Control ────────────────────────────────────────
@@ -180,12 +219,1478 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     println!();
     println!("[STORE] count: {} objects (after delete)", objstore::store::count());
 
+    // ── Audit Log ─────────────────────────────────────────────
+    println!();
+    println!("--- Audit Log (failed checks) ---");
+    for entry in caps::audit::dump().iter().filter(|e| e.result.is_err()) {
+        println!("  {}", entry);
+    }
+
     println!();
     println!("=== Object Store Demo Complete ===");
     println!();
-    println!("Exokernel ready. Halting CPU.");
 
-    halt_loop();
+    // ── Task Scheduler ────────────────────────────────────────
+    println!("=== Task Scheduler ===");
+    println!();
+
+    let mut sched = Scheduler::new();
+    sched.spawn("no-caps", 1, vec![], reader_task);
+    // The task drops its reference when it completes; keep ours for later
+    let reader_cap = cap_mgr::clone_ref(r_cap).unwrap_or(r_cap);
+    sched.spawn("reader", 2, vec![reader_cap], reader_task);
+    sched.run();
+
+    // Deliberately exhaust the heap to exercise the OOM diagnostic
+    #[cfg(feature = "oom-demo")]
+    {
+        println!();
+        println!("=== OOM Demo ===");
+        let hog: alloc::vec::Vec<u8> = alloc::vec::Vec::with_capacity(memory::heap::HEAP_SIZE * 2);
+        println!("[OOM] Unexpectedly allocated {} bytes", hog.capacity());
+    }
+
+    println!();
+    #[cfg(feature = "qemu-test")]
+    {
+        let code = if selftest(r_cap, rw_cap) {
+            arch::ExitCode::Success
+        } else {
+            arch::ExitCode::Failed
+        };
+        println!("[TEST] Exiting QEMU: {:?}", code);
+        arch::exit_qemu(code);
+    }
+
+    #[cfg(all(feature = "serial-echo", not(feature = "qemu-test")))]
+    {
+        println!("Exokernel ready. Serial echo mode.");
+        serial::echo_loop();
+    }
+
+    #[cfg(not(any(feature = "serial-echo", feature = "qemu-test")))]
+    {
+        println!("Exokernel ready. Halting CPU.");
+        halt_loop();
+    }
+}
+
+/// Demo task: read the Spanish greeting using only the caps it was granted.
+fn reader_task(step: u64, caps: &[CapId]) {
+    let id = objstore::ObjId::from_content(b"hola mundo!");
+    match caps.first() {
+        Some(&cap) => match obj::read(cap, id) {
+            Ok(o) => {
+                let text = core::str::from_utf8(&o.content).unwrap_or("(bin)");
+                println!("  [step {}] read {} with {} → ✓ \"{}\"", step, id, cap, text);
+            }
+            Err(e) => println!("  [step {}] read {} with {} → ✗ {}", step, id, cap, e),
+        },
+        None => println!("  [step {}] read {} → ✗ no capability held", step, id),
+    }
+}
+
+/// Integration check of the object store demo's end state, for
+/// `qemu-test` runs. Prints each check and returns whether all passed.
+#[cfg(feature = "qemu-test")]
+fn selftest(r_cap: CapId, rw_cap: CapId) -> bool {
+    use objstore::ObjId;
+
+    let hola = obj::read(r_cap, ObjId::from_content(b"hola mundo!"));
+    let remaining = objstore::store::count();
+    let hello_id = ObjId::from_content(b"hello");
+    let _ = obj::create(rw_cap, Object::new(b"hello"));
+    let hello_present = obj::contains(r_cap, hello_id);
+    let _ = objstore::store::delete(hello_id);
+    let before = arch::interrupts::ticks();
+    let via_syscall = syscall::invoke(syscall::nr::TICKS, [0; 4]);
+    let sys_content = b"syscall round trip";
+    let sys_created = syscall::invoke(
+        syscall::nr::STORE_CREATE,
+        [rw_cap.raw(), sys_content.as_ptr() as u64, sys_content.len() as u64, 0],
+    );
+    let mut sys_buf = [0u8; 4];
+    let sys_read = sys_created.and_then(|id| syscall::invoke(
+        syscall::nr::STORE_READ,
+        [rw_cap.raw(), id, sys_buf.as_mut_ptr() as u64, sys_buf.len() as u64],
+    ));
+    let after = arch::interrupts::ticks();
+    let kbd_cap = cap_mgr::mint(Resource::Device(0x60), Rights::READ, false);
+    let heap_byte = alloc::boxed::Box::new(0u8);
+    let phys = memory::translate(x86_64::VirtAddr::from_ptr(&*heap_byte));
+    let round_trip = phys.and_then(|p| memory::translate(memory::phys_to_virt(p)));
+    let old_heap_end = memory::heap::end();
+    let heap_grown = memory::grow_heap(4096).is_ok() && memory::heap::end() == old_heap_end + 4096;
+    // First-fit: keep allocating until a block lands in the new page
+    let mut fillers = alloc::vec::Vec::new();
+    let mut reached_new_page = false;
+    while heap_grown && !reached_new_page && fillers.len() < 400 {
+        let block = alloc::boxed::Box::new([0u8; 256]);
+        reached_new_page = (old_heap_end..old_heap_end + 4096).contains(&(block.as_ptr() as usize));
+        fillers.push(block);
+    }
+    drop(fillers);
+    let stats_before = cap_mgr::stats();
+    let minted = [(); 3].map(|_| cap_mgr::mint(Resource::Cpu(1), Rights::READ, false));
+    let _ = cap_mgr::revoke(minted[0]);
+    let stats_after = cap_mgr::stats();
+    let quota = cap_mgr::mint_with_quota(Resource::Object(9), Rights::READ, true, 3);
+    let quota_uses: alloc::vec::Vec<_> = (0..4).map(|_| cap_mgr::verify(quota, Rights::READ)).collect();
+    let quota_parent = cap_mgr::mint_with_quota(Resource::Object(9), Rights::READ, true, 3);
+    let quota_children: alloc::vec::Vec<CapId> = (0..3)
+        .filter_map(|_| cap_mgr::restrict(quota_parent, Rights::READ).ok())
+        .collect();
+    let child_uses = (0..4)
+        .filter(|i| quota_children.get(i % 3).is_some_and(|&c| cap_mgr::verify(c, Rights::READ).is_ok()))
+        .count();
+    let zero_refcount = {
+        let mut w = wire::Writer::new();
+        w.u32(1);
+        w.u64(0xDEAD_0000);
+        w.u8(2); // Resource::Object
+        w.u64(0);
+        w.u32(Rights::READ.bits());
+        w.u8(0);
+        w.u8(0);
+        for _ in 0..3 {
+            w.u8(0);
+            w.u64(0);
+        }
+        w.u32(0);
+        w.finish()
+    };
+    let shared = cap_mgr::mint(Resource::Object(10), Rights::READ, false);
+    let shared_lifecycle = [
+        cap_mgr::clone_ref(shared).map(|_| ()),
+        cap_mgr::drop_ref(shared),
+        cap_mgr::verify(shared, Rights::READ),
+        cap_mgr::drop_ref(shared),
+        cap_mgr::drop_ref(shared),
+    ];
+    let held = cap_mgr::mint(Resource::Object(11), Rights::READ, false);
+    let task_refs = {
+        let mut sched = Scheduler::new();
+        let finisher = sched.spawn("finisher", 1, vec![], idle_step);
+        let revoked = sched.spawn("revoked", 1, vec![], idle_step);
+        let granted = [finisher, revoked].map(|t| sched.grant(t, cap_mgr::clone_ref(held).unwrap_or(held)));
+        let dropped = sched.revoke_from(revoked, held);
+        sched.run();
+        [
+            granted == [true, true] && dropped,
+            cap_mgr::verify(held, Rights::READ).is_ok(),
+            cap_mgr::drop_ref(held).is_ok(),
+            cap_mgr::verify(held, Rights::READ) == Err(caps::CapError::NotFound),
+        ]
+    };
+    let read_only = cap_mgr::mint(Resource::Object(7), Rights::READ, false);
+    let write_only = cap_mgr::mint(Resource::Object(7), Rights::WRITE, false);
+    let other_object = cap_mgr::mint(Resource::Object(8), Rights::READ, false);
+    let group = cap_mgr::mint_group(&[
+        (Resource::Object(0), Rights::READ, false),
+        (Resource::Cpu(10), Rights::EXECUTE, false),
+    ]);
+    let members = cap_mgr::group_caps(group).unwrap_or_default();
+    let group_revoked = cap_mgr::revoke_group(group);
+    let _ = objstore::store::create(Object::new(b"selftest hi").with_tag("selftest-greeting"));
+    let _ = objstore::store::create(Object::new(b"selftest hey")
+        .with_tag("selftest-greeting")
+        .with_tag("selftest-important"));
+    let tag_deleted = objstore::store::delete_by_tag("selftest-greeting");
+    objstore::store::freeze();
+    let frozen_create = obj::create(rw_cap, Object::new(b"selftest frozen"));
+    objstore::store::unfreeze();
+    let thawed_create = obj::create(rw_cap, Object::new(b"selftest frozen"));
+    let _ = objstore::store::delete(ObjId::from_content(b"selftest frozen"));
+    objstore::store::set_memory_budget(objstore::store::memory_used() + 8);
+    let budget_fill = objstore::store::create(Object::new(b"budget!!"));
+    let budget_over = objstore::store::create(Object::new(b"x"));
+    objstore::store::set_memory_budget(usize::MAX);
+    let _ = objstore::store::delete(ObjId::from_content(b"budget!!"));
+    let prefix: alloc::vec::Vec<u8> = (0..12 * 1024u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect();
+    let big_a = [prefix.as_slice(), &b"tail A"[..]].concat();
+    let big_b = [prefix.as_slice(), &b"tail B"[..]].concat();
+    let chunked_a = objstore::store::create_chunked(&big_a);
+    let chunked_b = objstore::store::create_chunked(&big_b);
+    let first_chunk = |id| objstore::store::with_object(id, |m| m.content[..8].to_vec()).ok();
+    let pinned_chunk = ObjId::from_content(objstore::chunker::split(&big_a)[0]);
+    let pinned_delete = objstore::store::delete(pinned_chunk);
+    let stamped_early = objstore::store::create(Object::new(b"stamped early"));
+    let stamp_mark = arch::interrupts::ticks();
+    arch::interrupts::sleep_ticks(1);
+    let stamped_late = objstore::store::create(Object::new(b"stamped late"));
+    let created_after = objstore::store::query_created_after(stamp_mark);
+    use task::scheduler::TieBreak;
+    let by_id_runs = [tie_break_sequence(TieBreak::ById, false), tie_break_sequence(TieBreak::ById, false)];
+    let fifo_runs = [tie_break_sequence(TieBreak::Fifo, false), tie_break_sequence(TieBreak::Fifo, false)];
+    let edf_tie_runs = [tie_break_sequence(TieBreak::Fifo, true), tie_break_sequence(TieBreak::ById, true)];
+    let (sleep_start, rtc_start) = (arch::interrupts::ticks(), arch::rtc::now());
+    // 18 ticks of the ~18.2 Hz PIT is about a second
+    arch::interrupts::sleep_ticks(18);
+    let (sleep_end, rtc_end) = (arch::interrupts::ticks(), arch::rtc::now());
+    let day_second = |t: &arch::rtc::DateTime| {
+        u32::from(t.hour) * 3600 + u32::from(t.minute) * 60 + u32::from(t.second)
+    };
+    let rtc_elapsed = (day_second(&rtc_end) + 86_400 - day_second(&rtc_start)) % 86_400;
+    let legacy = objstore::store::create(Object::new(b"selftest legacy config").with_tag("config"))
+        .map(|(id, _)| id);
+    objstore::store::register_schema("config", &["version"]);
+    let legacy_before = legacy.and_then(objstore::store::read_meta).map(|m| m.tags);
+    let count_before_rollback = objstore::store::count();
+    let seq_of = |id| objstore::store::with_object(id, |o| o.inserted_seq).ok();
+    let legacy_seq = legacy.ok().and_then(seq_of);
+    let rolled_back = legacy.and_then(|id| objstore::store::transaction(&[
+        objstore::store::StoreOp::Delete(id),
+        objstore::store::StoreOp::Delete(id),
+    ]));
+    let legacy_restored = legacy.is_ok_and(|id| {
+        objstore::store::count() == count_before_rollback
+            && objstore::store::query_by_tag("config").contains(&id)
+            && objstore::store::read_meta(id).map(|m| m.tags) == legacy_before
+            && seq_of(id) == legacy_seq
+    });
+    if let Ok(id) = legacy {
+        let _ = objstore::store::delete(id);
+    }
+    let unversioned = objstore::store::create(Object::new(b"selftest config").with_tag("config"));
+    let untagged = objstore::store::create(Object::new(b"selftest untagged").with_tag("selftest-x"));
+    let config_via_transaction = untagged.and_then(|(id, _)| {
+        objstore::store::transaction(&[objstore::store::StoreOp::UpdateTags {
+            id,
+            add: vec![alloc::string::String::from("config")],
+            remove: vec![],
+        }])
+    });
+    let config_via_rename = objstore::store::rename_tag("selftest-x", "config");
+    let _ = objstore::store::delete_by_tag("selftest-x");
+    let irq3_claimed = arch::interrupts::register(arch::interrupts::PIC_1_OFFSET + 3, irq3_selftest);
+    // Simulate IRQ3 (vector 35) through its trampoline
+    unsafe { core::arch::asm!("int 35") };
+    let task_reads = {
+        let mut sched = Scheduler::new();
+        sched.spawn("no-caps", 1, vec![], cap_read_step);
+        sched.spawn("reader", 1, vec![cap_mgr::clone_ref(r_cap).unwrap_or(r_cap)], cap_read_step);
+        sched.run();
+        core::mem::take(&mut *CAP_READS.lock())
+    };
+    let expiring = {
+        let deadline = arch::interrupts::ticks() + 2;
+        let cap = cap_mgr::mint_with_expiry(Resource::Object(12), Rights::READ, false, deadline);
+        let early = cap_mgr::verify(cap, Rights::READ);
+        // Each sleep wakes on its target tick, so this stops on the
+        // deadline tick itself
+        while arch::interrupts::ticks() < deadline {
+            arch::interrupts::sleep_ticks(1);
+        }
+        let at_deadline = cap_mgr::verify(cap, Rights::READ);
+        arch::interrupts::sleep_ticks(1);
+        [early, at_deadline, cap_mgr::verify(cap, Rights::READ)]
+    };
+    let chain_root = cap_mgr::mint(Resource::Object(13), Rights::RW, true);
+    let chain_child = cap_mgr::restrict(chain_root, Rights::RW);
+    let chain_grandchild = chain_child.and_then(|c| cap_mgr::restrict(c, Rights::READ));
+    let single_root = cap_mgr::mint(Resource::Object(13), Rights::RW, true);
+    let single_child = cap_mgr::restrict(single_root, Rights::READ);
+    let _ = cap_mgr::revoke(single_root);
+    let chain_revoked = cap_mgr::revoke_tree(chain_root);
+    let listed_objects = [(); 3].map(|_| cap_mgr::mint(Resource::Object(14), Rights::READ, false));
+    let listed_device = cap_mgr::mint(Resource::Device(14), Rights::READ, false);
+    let _ = cap_mgr::revoke(cap_mgr::mint(Resource::Device(14), Rights::READ, false));
+    let region = cap_mgr::mint(Resource::Memory { base: 0x10_0000, size: 0x4000 }, Rights::RW, true);
+    let exact_fit = cap_mgr::restrict_memory(region, 0x10_0000, 0x4000, Rights::READ);
+    let sub_range = cap_mgr::restrict_memory(region, 0x10_1000, 0x1000, Rights::RW);
+    let overhang = cap_mgr::restrict_memory(region, 0x10_3000, 0x2000, Rights::READ);
+    let below = cap_mgr::restrict_memory(region, 0x0F_F000, 0x1000, Rights::READ);
+    let append_cap = cap_mgr::mint(Resource::Object(0), Rights::APPEND, false);
+    let log_id = objstore::store::create(Object::new(b"selftest log:")).map(|(id, _)| id);
+    let appended = log_id.map_err(objstore::gated::GatedError::Store)
+        .and_then(|id| obj::append(append_cap, id, b" more"));
+    let append_without_right = log_id.map_err(objstore::gated::GatedError::Store)
+        .and_then(|id| obj::append(rw_cap, id, b" denied"));
+    let log_after = log_id.and_then(objstore::store::read);
+    if let Ok(id) = log_id {
+        let _ = objstore::store::delete(id);
+    }
+    let shared_revoked = cap_mgr::mint(Resource::Object(10), Rights::READ, false);
+    let _ = cap_mgr::clone_ref(shared_revoked);
+    let _ = cap_mgr::revoke(shared_revoked);
+    caps::audit::clear();
+    let denied_create = obj::create(r_cap, Object::new(b"selftest audited"));
+    let audit_after_denial = caps::audit::dump();
+    let meta_doomed = objstore::store::create(Object::new(b"selftest meta").with_meta("lang", "es"));
+    let meta_with_doomed = objstore::store::query_by_meta("lang", "es").len();
+    if let Ok((id, _)) = meta_doomed {
+        let _ = objstore::store::delete(id);
+    }
+    let retagged = objstore::store::create(Object::new(b"selftest retag").with_tag("selftest-a")).map(|(id, _)| id);
+    let retag_noops = retagged.and_then(|id| objstore::store::update_tags(id, &["selftest-a", "selftest-b"], &["selftest-none"]));
+    let retag_tags = retagged.and_then(objstore::store::read_meta).map(|m| m.tags);
+    let retag_index_a = objstore::store::query_by_tag("selftest-a");
+    let retag_removed = retagged.and_then(|id| objstore::store::update_tags(id, &[], &["selftest-a"]));
+    let retag_read_only = retagged.map_err(objstore::gated::GatedError::Store)
+        .and_then(|id| obj::update_tags(r_cap, id, &["selftest-c"], &[]));
+    let retag_after = (objstore::store::query_by_tag("selftest-a"), objstore::store::query_by_tag("selftest-c"));
+    if let Ok(id) = retagged {
+        let _ = objstore::store::delete(id);
+    }
+    let small_inputs: [&[u8]; 6] = [b"", b"a", b"b", b"ab", b"ba", b"abc"];
+    let small_ids = small_inputs.map(ObjId::from_content);
+    let collision_id = ObjId::from_raw(0x5e1f_7e57_c011_1de5);
+    let mut planted = Object::new(b"selftest planted");
+    planted.id = collision_id;
+    let planted = objstore::store::create(planted);
+    let mut colliding = Object::new(b"selftest colliding");
+    colliding.id = collision_id;
+    let colliding = objstore::store::create(colliding);
+    let mut duplicate = Object::new(b"selftest planted");
+    duplicate.id = collision_id;
+    let duplicate = objstore::store::create(duplicate);
+    let _ = objstore::store::delete(collision_id);
+    let v1 = objstore::store::create(Object::new(b"selftest doc v1").with_tag("selftest-doc")).map(|(id, _)| id);
+    let v2 = v1.and_then(|id| objstore::store::create_version(id, b"selftest doc v2"));
+    let v3 = v2.and_then(|id| objstore::store::create_version(id, b"selftest doc v3"));
+    let read_only_version = v3.map(|id| obj::create_version(r_cap, id, b"selftest doc v4").is_err());
+    let doc_history = v3.map(|id| obj::history(r_cap, id));
+    let doc_v3_tags = v3.and_then(objstore::store::read_meta).map(|m| m.tags);
+    let _ = objstore::store::delete_by_tag("selftest-doc");
+    let tag_count = |tag: &str| objstore::store::list_tags().into_iter().find(|(t, _)| t == tag).map(|(_, n)| n);
+    let listed = [b"selftest listed 1".as_slice(), b"selftest listed 2"]
+        .map(|c| objstore::store::create(Object::new(c).with_tag("selftest-listed")).map(|(id, _)| id));
+    let mut listed_counts = vec![tag_count("selftest-listed")];
+    for id in listed.iter().flatten() {
+        let _ = objstore::store::delete(*id);
+        listed_counts.push(tag_count("selftest-listed"));
+    }
+    let mut sized = vec![0x5a; objstore::store::MAX_OBJECT_SIZE];
+    let at_limit_created = obj::create(rw_cap, Object::new(&sized));
+    let append_past_limit = at_limit_created.as_ref().ok().map(|&id| objstore::store::append(id, b"!"));
+    if let Ok(id) = &at_limit_created {
+        let _ = objstore::store::delete(*id);
+    }
+    sized.push(0x5a);
+    let over_limit = obj::create(rw_cap, Object::new(&sized));
+    drop(sized);
+    objstore::store::set_capacity(objstore::store::count() + 2);
+    let lru_a = objstore::store::create(Object::new(b"selftest lru a")).map(|(id, _)| id);
+    let lru_b = objstore::store::create(Object::new(b"selftest lru b")).map(|(id, _)| id);
+    // Read everything but `b`, leaving it least recently read
+    for id in objstore::store::list_by_insertion() {
+        if Ok(id) != lru_b {
+            let _ = objstore::store::read(id);
+        }
+    }
+    let lru_c = objstore::store::create_evicting(Object::new(b"selftest lru c"));
+    objstore::store::set_capacity(usize::MAX);
+    let lru_survivors = [lru_a, lru_b].map(|id| id.is_ok_and(objstore::store::contains));
+    for id in [lru_a, lru_c.map(|(id, _)| id)].into_iter().flatten() {
+        let _ = objstore::store::delete(id);
+    }
+    let (cursor_frames, nth_frames) = {
+        use x86_64::structures::paging::{FrameAllocator, Size4KiB};
+        // SAFETY: the mock frames are never touched, only handed out
+        let mut bump = unsafe { memory::frame_allocator::BootInfoFrameAllocator::new(&MOCK_REGIONS, None) };
+        let cursor: alloc::vec::Vec<_> = (0..1000).map(|_| FrameAllocator::<Size4KiB>::allocate_frame(&mut bump)).collect();
+        let nth: alloc::vec::Vec<_> = (0..1000).map(|i| mock_usable_frames().nth(i)).collect();
+        (cursor, nth)
+    };
+    let bitmap_checks = {
+        use x86_64::structures::paging::{FrameAllocator, PhysFrame};
+        let mut bitmap = memory::frame_allocator::BitmapFrameAllocator::new(&MOCK_REGIONS[..1]);
+        let frames: alloc::vec::Vec<PhysFrame> = core::iter::from_fn(|| bitmap.allocate_frame()).collect();
+        let reused = frames.get(5).copied();
+        let freed = reused.map(|f| bitmap.deallocate_frame(f));
+        let realloc = bitmap.allocate_frame();
+        let double_free = reused.map(|f| (bitmap.deallocate_frame(f), bitmap.deallocate_frame(f)));
+        let unmanaged = bitmap.deallocate_frame(PhysFrame::containing_address(x86_64::PhysAddr::new(0x20_0000)));
+        [
+            frames.len() == 256 && bitmap.total_frames() == 256,
+            freed == Some(Ok(())) && realloc == reused,
+            double_free == Some((Ok(()), Err(memory::frame_allocator::FrameError::DoubleFree))),
+            unmanaged == Err(memory::frame_allocator::FrameError::NotManaged),
+        ]
+    };
+    let span = |r: &Option<Resource>| match *r {
+        Some(Resource::Memory { base, size }) => Some((base, size)),
+        _ => None,
+    };
+    let reserved = [memory::reserve(3 * 4096), memory::reserve(5000)];
+    let spans = [span(&reserved[0]), span(&reserved[1])];
+    let released = spans[0].map(|(base, size)| memory::release(base, size));
+    let re_reserved = span(&memory::reserve(3 * 4096));
+    let double_release = spans[0].map(|(base, size)| {
+        let _ = memory::release(base, size);
+        memory::release(base, size)
+    });
+    if let Some((base, size)) = spans[1] {
+        let _ = memory::release(base, size);
+    }
+    let mem_stats = memory::stats();
+    let mock_counts = {
+        use x86_64::structures::paging::FrameAllocator;
+        let mut bitmap = memory::frame_allocator::BitmapFrameAllocator::new(&MOCK_REGIONS);
+        for _ in 0..10 {
+            let _ = bitmap.allocate_frame();
+        }
+        (bitmap.used_frames(), bitmap.total_frames())
+    };
+    let heap_guards = [
+        memory::heap::HEAP_START - memory::heap::GUARD_PAGE_SIZE,
+        memory::heap::end(),
+        memory::heap::end() + memory::heap::GUARD_PAGE_SIZE - 1,
+    ];
+    let heap_edges = [memory::heap::HEAP_START, memory::heap::end() - 1];
+    // Press and release 'h', then 'i'
+    while arch::interrupts::read_key().is_some() {}
+    for scancode in [0x23, 0xA3, 0x17, 0x97] {
+        arch::interrupts::feed_scancode(scancode);
+    }
+    let typed = [arch::interrupts::read_key(), arch::interrupts::read_key(), arch::interrupts::read_key()];
+    let mut line_buf = [0u8; 8];
+    let line_len = serial_loopback_line(b"hi\r", &mut line_buf);
+    let (info_echo, warn_echo) = with_serial_loopback(|| {
+        log::set_level(log::Level::Warn);
+        crate::info!("selftest: suppressed");
+        let info = drain_serial();
+        crate::warn!("selftest: shown");
+        let warn = drain_serial();
+        log::set_level(log::Level::Info);
+        (info, warn)
+    });
+    let fuel_run = {
+        let mut sched = Scheduler::new();
+        // Enough credit for every step, so only fuel cuts its turns short
+        let a = sched.spawn_with_weight("log-a", 4, 4, vec![], log_a);
+        sched.spawn("log-b", 2, vec![], log_b);
+        sched.set_fuel(a, 2);
+        sched.run();
+        core::mem::take(&mut *TIE_BREAK_LOG.lock())
+    };
+    let sleep_log = {
+        let mut sched = Scheduler::new();
+        sched.spawn("sleeper", 2, vec![], sleepy_step);
+        sched.spawn("log-b", 2, vec![], log_b);
+        sched.run();
+        let _ = core::mem::take(&mut *TIE_BREAK_LOG.lock());
+        core::mem::take(&mut *SLEEP_LOG.lock())
+    };
+    let (yield_run, yield_stats) = {
+        let mut sched = Scheduler::new();
+        sched.spawn("yielder", 2, vec![], yield_every_other);
+        sched.spawn("log-b", 2, vec![], log_b);
+        sched.run();
+        (core::mem::take(&mut *TIE_BREAK_LOG.lock()), sched.stats())
+    };
+    let (ipc_tx, ipc_rx) = task::ipc::channel(2);
+    let ipc_sends = [ipc_tx.send(vec![1]).is_ok(), ipc_tx.send(vec![2]).is_ok()];
+    let ipc_full = ipc_tx.send(vec![3]).map_err(|task::ipc::Full(msg)| msg);
+    let ipc_received = [ipc_rx.recv(), ipc_rx.recv(), ipc_rx.recv()];
+    let (listed_before, listed_after, listed_ids) = {
+        let mut sched = Scheduler::new();
+        let sleeper = sched.spawn("sleeper", 2, vec![], sleepy_step);
+        let logger = sched.spawn("log-b", 1, vec![], log_b);
+        let before = sched.list();
+        // Leaves the sleeper asleep after its first step
+        sched.run_until_idle(1);
+        let after = sched.list();
+        let _ = core::mem::take(&mut *TIE_BREAK_LOG.lock());
+        let _ = core::mem::take(&mut *SLEEP_LOG.lock());
+        (before, after, (sleeper, logger))
+    };
+    let counted = {
+        let mut sched = Scheduler::new();
+        let a = sched.spawn("log-a", 3, vec![], log_a);
+        let b = sched.spawn("log-b", 3, vec![], log_b);
+        sched.run();
+        let _ = core::mem::take(&mut *TIE_BREAK_LOG.lock());
+        let stats = sched.stats();
+        (stats.turns, stats.steps, stats.per_task.get(&a).copied(), stats.per_task.get(&b).copied())
+    };
+    let dom_region = cap_mgr::mint(Resource::Memory { base: 0x20_0000, size: 0x4000 }, Rights::RW, false);
+    let dom_inner = cap_mgr::mint(Resource::Memory { base: 0x20_1000, size: 0x1000 }, Rights::READ, false);
+    let dom_overhang = cap_mgr::mint(Resource::Memory { base: 0x20_3000, size: 0x2000 }, Rights::READ, false);
+    let dom_rw = cap_mgr::mint(Resource::Object(15), Rights::RW, false);
+    let dom_r = cap_mgr::mint(Resource::Object(15), Rights::READ, false);
+    let dom_other = cap_mgr::mint(Resource::Object(16), Rights::READ, false);
+    let batch_specs = [
+        (Resource::Object(17), Rights::READ, false),
+        (Resource::Device(17), Rights::RW, true),
+        (Resource::Cpu(17), Rights::EXECUTE, false),
+    ];
+    let batch = cap_mgr::mint_batch(&batch_specs);
+    let sealed = cap_mgr::seal(r_cap);
+    let (handed_off, transfer_to_self, transfer_unheld) = {
+        let write_cap = cap_mgr::mint(Resource::Object(18), Rights::WRITE, false);
+        let mut sched = Scheduler::new();
+        let producer = sched.spawn("producer", 1, vec![write_cap], caps_step);
+        let consumer = sched.spawn("consumer", 1, vec![], caps_step);
+        let moved = sched.transfer(write_cap, producer, consumer);
+        let to_self = sched.transfer(write_cap, consumer, consumer);
+        let unheld = sched.transfer(write_cap, producer, consumer);
+        // The reference moves with the cap, so it is still alive here
+        let alive = cap_mgr::describe(write_cap).is_ok();
+        sched.run();
+        let held = core::mem::take(&mut *TASK_CAPS.lock());
+        (moved.is_ok() && alive && held == [vec![], vec![write_cap]], to_self, unheld)
+    };
+    let copy_src_id = obj::create(rw_cap, Object::new(b"hello").with_tag("selftest-copy"));
+    let copied = copy_src_id.as_ref().map(|&src| obj::copy(rw_cap, src, |content| content.push(b'!')));
+    let copied_obj = objstore::store::read(ObjId::from_content(b"hello!"));
+    let copy_unchanged = copy_src_id.as_ref().map(|&src| obj::copy(rw_cap, src, |_| {}));
+    let _ = objstore::store::delete(ObjId::from_content(b"hello!"));
+    let _ = objstore::store::delete(hello_id);
+    let snap_id = objstore::store::create(Object::new(b"selftest snapshot")).map(|(id, _)| id);
+    let snap = objstore::store::snapshot();
+    let snap_count = snap.count();
+    let snap_deleted = snap_id.and_then(objstore::store::delete);
+    let snap_count_deleted = objstore::store::count();
+    objstore::store::restore(snap);
+    let snap_count_restored = objstore::store::count();
+    let snap_restored = snap_id.is_ok_and(objstore::store::contains);
+    let _ = snap_id.and_then(objstore::store::delete);
+    let hola_id = ObjId::from_content(b"hola mundo!");
+    let hola_meta = obj::read_meta(r_cap, hola_id);
+    let hola_len = obj::with_object(r_cap, hola_id, |o| o.content.len());
+    let green = obj::create(rw_cap, Object::new(b"selftest green").with_tag("green"));
+    let grz = obj::create(rw_cap, Object::new(b"selftest grz").with_tag("grz"));
+    let gre_tags = obj::query_tag_prefix(r_cap, "gre");
+    let all_prefixed = objstore::store::query_tag_prefix("").len() == objstore::store::list_tags().len();
+    for id in [&green, &grz].into_iter().flatten() {
+        let _ = objstore::store::delete(*id);
+    }
+    let frame_zeroed = match memory::reserve(4096) {
+        Some(Resource::Memory { base, size }) => {
+            let page = memory::phys_to_virt(x86_64::PhysAddr::new(base)).as_mut_ptr::<u8>();
+            // Dirty the frame, then get it back from a fresh reservation
+            unsafe { core::ptr::write_bytes(page, 0xAA, 4096) };
+            let _ = memory::release(base, size);
+            let again = memory::reserve(4096);
+            let zeroed = again == Some(Resource::Memory { base, size })
+                && unsafe { core::slice::from_raw_parts(page, 4096) }.iter().all(|&b| b == 0);
+            let _ = memory::release(base, size);
+            zeroed
+        }
+        _ => false,
+    };
+    let huge_4m = memory::heap::huge_layout(0x4000_0000, 4 * 1024 * 1024);
+    let huge_unaligned = memory::heap::huge_layout(0x4000_1000, 4 * 1024 * 1024);
+    let full_quota = cap_mgr::mint_with_quota(Resource::Object(19), Rights::RW, true, 5);
+    let full_expiring = cap_mgr::mint_with_expiry(Resource::Device(19), Rights::READ, false, u64::MAX);
+    let full_child = cap_mgr::restrict(full_quota, Rights::READ);
+    let _ = cap_mgr::revoke(full_expiring);
+    // Object(7) is taken by the effective_rights check, so use a fresh id
+    let doomed_caps = [Rights::READ, Rights::WRITE, Rights::RW].map(|r| cap_mgr::mint(Resource::Object(20), r, false));
+    let resource_revoked = cap_mgr::revoke_for_resource(&Resource::Object(20));
+    let merged_first = objstore::store::get_or_create(Object::new(b"hello").with_tag("selftest-x"));
+    let merged_second = objstore::store::get_or_create(Object::new(b"hello").with_tag("selftest-y").with_tag("selftest-x"));
+    let merged_tags = objstore::store::read_meta(hello_id).map(|m| m.tags);
+    let _ = objstore::store::delete(hello_id);
+    let exec_cap = cap_mgr::mint(Resource::Object(0), Rights::EXECUTE, false);
+    let exec_id = objstore::store::create(Object::new(b"selftest exec")).map(|(id, _)| id);
+    let exec_unregistered = exec_id.map(|id| obj::execute(exec_cap, id));
+    objstore::store::set_executor(uppercase_executor);
+    let exec_output = exec_id.map(|id| obj::execute(exec_cap, id));
+    let exec_read_only = exec_id.map(|id| obj::execute(r_cap, id));
+    let _ = exec_id.and_then(objstore::store::delete);
+    let meta_full = (0..objstore::MAX_META_KEYS).try_fold(Object::new(b"selftest meta"), |o, i| {
+        o.try_with_meta(&alloc::format!("k{}", i), "v")
+    });
+    let meta_overwrite = meta_full.clone().and_then(|o| o.try_with_meta("k0", "w"));
+    let meta_one_more = meta_full.and_then(|o| o.try_with_meta("extra", "v"));
+    let long_value = "x".repeat(objstore::MAX_META_VALUE_LEN + 1);
+    let meta_too_long = Object::new(b"selftest meta").try_with_meta("k", &long_value);
+    let meta_truncated = Object::new(b"selftest meta").with_meta("k", &long_value);
+    let seq_ids = [&b"selftest seq 1"[..], b"selftest seq 2", b"selftest seq 3"]
+        .map(|content| objstore::store::create(Object::new(content)).map(|(id, _)| id));
+    let seq_deleted = seq_ids[1].and_then(objstore::store::delete);
+    let seq_order: alloc::vec::Vec<ObjId> = objstore::store::list_by_insertion()
+        .into_iter()
+        .filter(|id| seq_ids.contains(&Ok(*id)))
+        .collect();
+    for id in [seq_ids[0], seq_ids[2]].into_iter().flatten() {
+        let _ = objstore::store::delete(id);
+    }
+    let bcd_decoded = [0x00, 0x09, 0x10, 0x59, 0x99].map(arch::rtc::bcd_to_binary);
+    let dumped = alloc::format!("{}", serial::HexDump(b"hexdump selftest\x00\x01\xfe~"));
+    let (com2_echo, com1_echo) = with_serial_loopback(|| {
+        crate::print_to!(1, "selftest: COM2");
+        let com2 = drain_serial();
+        crate::print_to!(0, "selftest: COM1");
+        (com2, drain_serial())
+    });
+    let weighted_share = {
+        let mut sched = Scheduler::new();
+        sched.spawn_with_weight("log-a", 300, 3, vec![], log_a);
+        sched.spawn("log-b", 300, vec![], log_b);
+        sched.run();
+        // The first 100 turns: both tasks are still running throughout
+        let log = core::mem::take(&mut *TIE_BREAK_LOG.lock());
+        let window = &log[..200];
+        let a_steps = window.iter().filter(|&&t| t == b'a').count();
+        (a_steps, window.len() - a_steps)
+    };
+    let (edf_run, edf_missed) = {
+        let now = arch::interrupts::ticks();
+        let mut sched = Scheduler::new();
+        let a = sched.spawn("log-a", 1, vec![], log_a);
+        let b = sched.spawn("log-b", 2, vec![], log_b);
+        let c = sched.spawn("log-c", 1, vec![], log_c);
+        sched.set_deadline(a, now + 3000);
+        sched.set_deadline(b, now + 1000);
+        sched.set_deadline(c, now + 2000);
+        sched.run_edf();
+        (core::mem::take(&mut *TIE_BREAK_LOG.lock()), sched.stats().missed)
+    };
+    let (export_cleared, export_round_trip) = {
+        let saved = objstore::store::snapshot();
+        // Newest first, so manifests go before the chunks they pin
+        for id in objstore::store::list_by_insertion().into_iter().rev() {
+            let _ = objstore::store::delete(id);
+        }
+        let cleared = objstore::store::count() == 0;
+        let originals = [
+            Object::new(b"selftest export 1").with_tag("selftest-export"),
+            Object::new(b"selftest export 2").with_tag("selftest-export").with_meta("k", "v"),
+            Object::new(b"selftest export 3"),
+        ];
+        let key = |o: &Object| (o.id, o.content.clone(), o.tags.clone(), o.metadata.clone());
+        let expected: alloc::vec::Vec<_> = originals.iter().map(key).collect();
+        let created = originals.into_iter().all(|o| objstore::store::create(o).is_ok());
+        let buf = objstore::store::export();
+        for (id, ..) in &expected {
+            let _ = objstore::store::delete(*id);
+        }
+        let emptied = objstore::store::count() == 0;
+        let imported = objstore::store::import(&buf);
+        let reloaded: alloc::vec::Vec<_> = expected.iter()
+            .filter_map(|(id, ..)| objstore::store::read(*id).ok())
+            .map(|o| key(&o))
+            .collect();
+        objstore::store::restore(saved);
+        (cleared, created && emptied && imported == Ok(3) && reloaded == expected)
+    };
+    let panic_echo = with_serial_loopback(|| {
+        // As if the panic hit while something was printing
+        let held = serial::serial1().lock();
+        crate::panic_println!("selftest: panic path");
+        drop(held);
+        drain_serial()
+    });
+    let hola_chunks = [(5, 3), (5, 100), (11, 4), (50, 4)].map(|(offset, len)| obj::read_chunk(r_cap, hola_id, offset, len));
+    let hash_pairs = [
+        (Resource::Memory { base: 0x1000, size: 0x2000 }, Resource::Memory { base: 0x2000, size: 0x1000 }),
+        (Resource::Device(1), Resource::Object(1)),
+        (Resource::Cpu(5), Resource::Cpu(6)),
+    ];
+    let indexed_caps = {
+        let resources = [Resource::Object(21), Resource::Memory { base: 0x30_0000, size: 0x2000 }];
+        let kept = cap_mgr::mint(resources[0].clone(), Rights::RW, true);
+        let dropped = cap_mgr::mint(resources[0].clone(), Rights::READ, false);
+        let _ = cap_mgr::restrict(kept, Rights::READ);
+        let region = cap_mgr::mint(resources[1].clone(), Rights::RW, true);
+        let _ = cap_mgr::restrict_memory(region, 0x30_1000, 0x1000, Rights::READ);
+        let _ = cap_mgr::revoke(dropped);
+        resources.map(|resource| {
+            let mut indexed = cap_mgr::list_for_resource(&resource);
+            indexed.sort_unstable();
+            let scanned: alloc::vec::Vec<CapId> = cap_mgr::list_all()
+                .into_iter()
+                .filter(|(id, r, _)| *r == resource && cap_mgr::describe_full(*id).is_ok_and(|info| !info.revoked))
+                .map(|(id, ..)| id)
+                .collect();
+            (indexed, scanned)
+        })
+    };
+    let dup_first = objstore::store::create(Object::new(b"selftest dup").with_tag("selftest-dup-a"));
+    let dup_second = objstore::store::create(Object::new(b"selftest dup").with_tag("selftest-dup-b").with_meta("selftest", "dup"));
+    let dup_index = (
+        objstore::store::query_by_tag("selftest-dup-b").is_empty(),
+        objstore::store::query_by_meta("selftest", "dup").is_empty(),
+    );
+    if let Ok((id, _)) = dup_first {
+        let _ = objstore::store::delete(id);
+    }
+    let (metered_state, metered_steps) = {
+        let cpu_cap = cap_mgr::mint(Resource::Cpu(10), Rights::EXECUTE, false);
+        let mut sched = Scheduler::new();
+        let metered = sched.spawn_gated(cpu_cap, "spinner", 100, vec![], spin_one_tick);
+        sched.run();
+        let steps = sched.list().iter().find(|t| Ok(t.0) == metered).map(|t| t.3);
+        (metered.ok().and_then(|id| sched.task_state(id)), steps)
+    };
+    let subscribed = objstore::store::subscribe(record_selftest_event);
+    let watched = objstore::store::create(Object::new(b"selftest subscribe")).map(|(id, _)| id);
+    let _ = watched.and_then(objstore::store::delete);
+    let delivered = core::mem::take(&mut *STORE_EVENTS.lock());
+    let reclaim_checks = {
+        use x86_64::structures::paging::{FrameAllocator, PhysFrame};
+        let mut bitmap = memory::frame_allocator::BitmapFrameAllocator::new(&MOCK_REGIONS);
+        let before = core::iter::from_fn(|| bitmap.allocate_frame()).count();
+        let map_frame = PhysFrame::containing_address(x86_64::PhysAddr::new(0x50_0000));
+        // Safety: nothing is mapped from the synthetic map; pretend its
+        // first bootloader frame still holds the memory map
+        let freed = unsafe { bitmap.reclaim(|frame| frame == map_frame) };
+        let after: alloc::vec::Vec<PhysFrame> = core::iter::from_fn(|| bitmap.allocate_frame()).collect();
+        let again = unsafe { bitmap.reclaim(|_| false) };
+        [
+            before == 1280,
+            freed == 255 && after.len() == 255 && !after.contains(&map_frame) && bitmap.total_frames() == 1536,
+            again == 0,
+        ]
+    };
+    let renamed = obj::rename_tag(rw_cap, "greeting", "hello");
+    let renamed_queries = (objstore::store::query_by_tag("hello"), objstore::store::query_by_tag("greeting"));
+    let renamed_to_self = obj::rename_tag(rw_cap, "hello", "hello");
+    let renamed_back = obj::rename_tag(rw_cap, "hello", "greeting");
+    let version_start = objstore::store::version();
+    let versioned = objstore::store::create(Object::new(b"selftest version"));
+    let version_retag = versioned.and_then(|(id, _)| objstore::store::update_tags(id, &["selftest-version"], &[]));
+    let version_delete = versioned.and_then(|(id, _)| objstore::store::delete(id));
+    let version_end = objstore::store::version();
+    while arch::interrupts::read_key().is_some() {}
+    let dropped_before = arch::interrupts::dropped_key_count();
+    for _ in 0..arch::interrupts::KEY_QUEUE_CAPACITY + 5 {
+        arch::interrupts::feed_scancode(0x23);
+    }
+    arch::interrupts::feed_scancode(0xA3);
+    let dropped_delta = arch::interrupts::dropped_key_count() - dropped_before;
+    let flooded = core::iter::from_fn(arch::interrupts::read_key).count();
+    let on_stack = 0u8;
+    let rsp = &on_stack as *const u8 as u64;
+    let df_stack = arch::gdt::ist_stack(arch::gdt::DOUBLE_FAULT_IST_INDEX);
+    // An id whose content has since changed, as a stray write would leave it
+    let rotted_id = ObjId::from_content(b"selftest intact");
+    let rotted = {
+        let mut w = wire::Writer::new();
+        w.u32(1);
+        w.u64(rotted_id.raw());
+        w.bytes(b"selftest intacT");
+        w.u32(0);
+        w.u32(0);
+        w.u8(0);
+        objstore::store::import(&w.finish())
+    };
+    let integrity = (objstore::store::verify_integrity(rotted_id), objstore::store::verify_integrity(hola_id));
+    let corrupted = objstore::store::verify_all();
+    let _ = objstore::store::delete(rotted_id);
+    let divisors = [9600, 38_400, 115_200, 7, 0, 230_400].map(serial::divisor_for);
+    let fast = caps::FastCap::new(cap_mgr::mint(Resource::Object(22), Rights::READ, false));
+    let fast_warm = [cap_mgr::verify_cached(&fast, Rights::READ), cap_mgr::verify_cached(&fast, Rights::READ)];
+    let fast_denied = cap_mgr::verify_cached(&fast, Rights::WRITE);
+    cap_mgr::revoke_for_resource(&Resource::Object(22));
+    let fast_revoked = cap_mgr::verify_cached(&fast, Rights::READ);
+    let (idle_halts, idle_stats) = {
+        let mut sched = Scheduler::new();
+        // Asleep for 5 ticks between its steps; halting must not miss the wake-up
+        sched.spawn("sleeper", 2, vec![], sleepy_step);
+        let halts = sched.run_until_idle(10);
+        let _ = core::mem::take(&mut *SLEEP_LOG.lock());
+        (halts, sched.stats())
+    };
+    let mundo = obj::query_by_content(r_cap, b"mundo");
+    let cyclic_imports = [&[(0xDEAD_1000, 0xDEAD_1001), (0xDEAD_1001, 0xDEAD_1000)][..], &[(0xDEAD_1002, 0xDEAD_1002)]]
+        .map(|links| {
+            let mut w = wire::Writer::new();
+            w.u32(links.len() as u32);
+            for &(id, parent) in links {
+                w.u64(id);
+                w.u8(2); // Resource::Object
+                w.u64(23);
+                w.u32(Rights::READ.bits());
+                w.u8(1);
+                w.u8(0);
+                // No expiry, the given parent, no quota
+                for (present, value) in [(0, 0), (1, parent), (0, 0)] {
+                    w.u8(present);
+                    w.u64(value);
+                }
+                w.u32(1);
+            }
+            cap_mgr::import(&w.finish())
+        });
+    let mut tag_counts = alloc::vec::Vec::new();
+    let counted_ids: alloc::vec::Vec<ObjId> = [&b"selftest count 1"[..], b"selftest count 2"]
+        .iter()
+        .filter_map(|content| {
+            let created = objstore::store::create(Object::new(content).with_tag("selftest-count"));
+            tag_counts.push(obj::count_by_tag(r_cap, "selftest-count").ok());
+            created.ok().map(|(id, _)| id)
+        })
+        .collect();
+    for id in &counted_ids {
+        let _ = objstore::store::delete(*id);
+        tag_counts.push(obj::count_by_tag(r_cap, "selftest-count").ok());
+    }
+    let named = cap_mgr::mint_named("selftest-console", Resource::Device(0x3F8), Rights::RW, false);
+    let renamed_mint = cap_mgr::mint_named("selftest-console", Resource::Device(0x2F8), Rights::RW, false);
+    let ttl_base = objstore::store::count();
+    let ttl_objects = [&b"selftest ttl read"[..], b"selftest ttl sweep 1", b"selftest ttl sweep 2"]
+        .map(|content| objstore::store::create(Object::new(content).with_ttl(0)).map(|(id, _)| id));
+    let ttl_live = objstore::store::create(Object::new(b"selftest ttl live").with_ttl(10_000)).map(|(id, _)| id);
+    arch::interrupts::sleep_ticks(2);
+    let ttl_overcount = objstore::store::count();
+    let ttl_read = ttl_objects[0].map(|id| obj::read(r_cap, id));
+    let ttl_after_read = objstore::store::count();
+    let ttl_swept = objstore::store::sweep_expired();
+    let ttl_after_sweep = objstore::store::count();
+    let _ = ttl_live.and_then(objstore::store::delete);
+    let wake_run = {
+        let mut sched = Scheduler::new();
+        sched.spawn("sleeper", 2, vec![], nap_two_ticks);
+        sched.spawn("tick-a", 3, vec![], tick_a);
+        sched.spawn("tick-b", 3, vec![], tick_b);
+        sched.run();
+        core::mem::take(&mut *TIE_BREAK_LOG.lock())
+    };
+    let (kill_run, kill_gone, kill_again) = {
+        let caps = [24, 25, 26].map(|n| cap_mgr::mint(Resource::Object(n), Rights::READ, false));
+        let mut sched = Scheduler::new();
+        let ids = caps.map(|cap| sched.spawn("killable", 1, vec![cap], caps_step));
+        let killed = sched.kill(ids[1]);
+        // Killing drops the task's reference, the only one
+        let gone = cap_mgr::describe(caps[1]).is_err();
+        sched.run();
+        let held = core::mem::take(&mut *TASK_CAPS.lock());
+        (killed && held == [vec![caps[0]], vec![caps[2]]], gone, sched.kill(ids[1]))
+    };
+    let (self_kill_steps, self_kill_gone) = {
+        let cap = cap_mgr::mint(Resource::Object(27), Rights::READ, false);
+        let mut sched = Scheduler::new();
+        let id = sched.spawn("self-kill", 3, vec![cap], kill_self_step);
+        *KILL_TARGET.lock() = Some(id);
+        sched.run();
+        (core::mem::take(&mut *TASK_CAPS.lock()).len(), cap_mgr::describe(cap).is_err())
+    };
+    let (last_mint, exhausted_mint) = {
+        let saved = CapId::set_next_for_test(u64::MAX - 1);
+        let last = CapId::try_mint();
+        let exhausted = CapId::try_mint();
+        CapId::set_next_for_test(saved);
+        (last, exhausted)
+    };
+    let ttl_tagged = objstore::store::create(Object::new(b"selftest ttl tagged").with_tag("selftest-ttl").with_ttl(0))
+        .map(|(id, _)| id);
+    arch::interrupts::sleep_ticks(2);
+    let ttl_tag_count = objstore::store::count_by_tag("selftest-ttl");
+    let ttl_content_hits = objstore::store::query_by_content(b"selftest ttl tagged");
+    let ttl_tagged_gone = ttl_tagged.is_ok_and(|id| !objstore::store::contains(id));
+    let checks = [
+        ("two objects remain after delete", remaining == 2),
+        ("deleted object is gone", obj::read(r_cap, ObjId::from_content(b"hello")).is_err()),
+        ("contains finds re-created hello", hello_present.is_ok_and(|present| present)),
+        ("R cap reads surviving object", hola.is_ok_and(|o| o.content == b"hola mundo!")),
+        ("R cap cannot create", obj::create(r_cap, Object::new(b"selftest")).is_err()),
+        ("tag query finds greeting", obj::query_by_tag(r_cap, "greeting").is_ok_and(|ids| ids.len() == 1)),
+        ("RW cap cannot delete", obj::delete(rw_cap, ObjId::from_content(b"hola mundo!")).is_err()),
+        ("SipHash-2-4 matches the reference vectors", {
+            let key = (0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908);
+            let msg: [u8; 15] = core::array::from_fn(|i| i as u8);
+            objstore::siphash::siphash::<2, 4>(key.0, key.1, &[]) == 0x726f_db47_dd0e_0e31
+                && objstore::siphash::siphash::<2, 4>(key.0, key.1, &msg) == 0xa129_ca61_49be_45e5
+        }),
+        ("content ids hash whole words and the tail", ObjId::from_content(b"").raw() == 0x2cb6_6ebb_0307_9c08
+            && ObjId::from_content(b"exactly8").raw() == 0xcf22_b4f7_fd66_6269
+            && ObjId::from_content(b"twelve bytes").raw() == 0x80a6_09ee_66e1_7a32),
+        ("int 0x80 returns tick count", via_syscall.is_ok_and(|t| (before..=after).contains(&t))),
+        ("int 0x80 STORE_READ truncates to a small buffer", sys_read == Ok(sys_content.len() as u64)
+            && sys_buf == sys_content[..4]),
+        ("device 0x60 cap cannot read COM1", devices::gated::port_in(kbd_cap, 0x3F8).is_err()),
+        ("phys_to_virt/translate round-trip", phys.is_some() && round_trip == phys),
+        ("grown heap page is allocated into", heap_grown && reached_new_page),
+        ("stats count mint x3, revoke x1", stats_after.total - stats_before.total == 3
+            && stats_after.live - stats_before.live == 2
+            && stats_after.revoked - stats_before.revoked == 1),
+        ("quota-3 cap verifies three times", quota_uses[..3].iter().all(Result::is_ok)
+            && quota_uses[3] == Err(caps::CapError::QuotaExhausted)),
+        ("restricted children share the parent's quota", quota_children.len() == 3 && child_uses == 3),
+        ("import rejects a zero refcount", cap_mgr::import(&zero_refcount) == Err(caps::CapError::Corrupt)),
+        ("tasks drop their cap refs on completion and revoke_from", task_refs.iter().all(|&ok| ok)),
+        ("refcounted cap survives until the last drop", shared_lifecycle[..4].iter().all(Result::is_ok)
+            && shared_lifecycle[4] == Err(caps::CapError::NotFound)),
+        ("combine rejects mismatched resources",
+            cap_mgr::combine(read_only, other_object) == Err(caps::CapError::ResourceMismatch)),
+        ("effective_rights unions R and W into RW",
+            cap_mgr::effective_rights(&[read_only, write_only, other_object], &Resource::Object(7)) == Rights::RW),
+        ("revoke_group revokes every member", members.len() == 2
+            && group_revoked == Ok(2)
+            && members.iter().all(|&c| cap_mgr::verify(c, Rights::empty()) == Err(caps::CapError::Revoked))),
+        ("delete_by_tag clears every tag index", tag_deleted == Ok(2)
+            && objstore::store::count_by_tag("selftest-important") == 0),
+        ("create fails while frozen", matches!(
+            frozen_create,
+            Err(objstore::gated::GatedError::Store(objstore::ObjError::Frozen)),
+        )),
+        ("create succeeds after unfreeze", thawed_create.is_ok()),
+        ("create up to the memory budget succeeds", budget_fill.is_ok()),
+        ("create past the memory budget fails", budget_over == Err(objstore::ObjError::BudgetExceeded)),
+        ("sleep_ticks(18) takes about a second", sleep_end - sleep_start >= 18 && rtc_elapsed <= 2),
+        ("config object without version is rejected",
+            unversioned == Err(objstore::ObjError::SchemaViolation)),
+        ("failed transaction restores a deleted object", rolled_back == Err(objstore::ObjError::NotFound)
+            && legacy_restored),
+        ("transaction cannot tag config without version",
+            config_via_transaction == Err(objstore::ObjError::SchemaViolation)),
+        ("rename_tag cannot tag config without version",
+            config_via_rename == Err(objstore::ObjError::SchemaViolation)),
+        ("registered IRQ3 handler is invoked", irq3_claimed
+            && IRQ3_FIRED.load(core::sync::atomic::Ordering::Relaxed)),
+        ("chunked objects reassemble on read", chunked_a.is_ok_and(|id| {
+            objstore::store::read(id).is_ok_and(|o| o.content == big_a)
+        })),
+        ("ById runs identical task sets identically", by_id_runs[0] == b"aabab" && by_id_runs[1] == b"aabab"),
+        ("Fifo runs identical task sets identically", fifo_runs[0] == b"aabab" && fifo_runs[1] == b"aabab"),
+        ("EDF deadline ties run in id order", edf_tie_runs.iter().all(|run| run == b"aaabb")),
+        ("shared prefix shares chunk objects", match (chunked_a, chunked_b) {
+            (Ok(a), Ok(b)) => a != b && first_chunk(a).is_some() && first_chunk(a) == first_chunk(b),
+            _ => false,
+        }),
+        ("query_created_after splits at the given tick", match (stamped_early, stamped_late) {
+            (Ok((early, _)), Ok((late, _))) => created_after.contains(&late) && !created_after.contains(&early),
+            _ => false,
+        }),
+        ("chunk listed by a manifest can't be deleted", pinned_delete == Err(objstore::ObjError::InUse)
+            && chunked_a.is_ok_and(|id| objstore::store::read(id).is_ok_and(|o| o.content == big_a))),
+        ("only the task granted a READ cap can read", task_reads == [false, true]),
+        ("expiring cap is valid through its deadline tick", expiring[..2].iter().all(Result::is_ok)),
+        ("expiring cap fails once the deadline passes", expiring[2] == Err(caps::CapError::Expired)),
+        ("revoke leaves a restricted child valid", single_child.is_ok_and(|c| cap_mgr::verify(c, Rights::READ).is_ok())),
+        ("revoke_tree reaches a grandchild", chain_revoked == Ok(3)
+            && chain_grandchild.is_ok_and(|c| cap_mgr::verify(c, Rights::READ) == Err(caps::CapError::Revoked))),
+        ("list_for_resource partitions by resource", cap_mgr::list_for_resource(&Resource::Object(14)) == listed_objects
+            && cap_mgr::list_for_resource(&Resource::Device(14)) == [listed_device]),
+        ("list_all includes every listed cap", [listed_objects[0], listed_objects[1], listed_objects[2], listed_device]
+            .iter()
+            .all(|id| cap_mgr::list_all().iter().any(|(c, _, _)| c == id))),
+        ("restrict_memory accepts an exact fit and a sub-range", exact_fit.is_ok()
+            && sub_range.and_then(cap_mgr::describe)
+                == Ok((Resource::Memory { base: 0x10_1000, size: 0x1000 }, Rights::RW))),
+        ("restrict_memory rejects out-of-bounds ranges", overhang == Err(caps::CapError::CannotEscalate)
+            && below == Err(caps::CapError::CannotEscalate)),
+        ("append keeps the id and extends the content", appended.is_ok()
+            && log_after.is_ok_and(|o| Ok(o.id) == log_id && o.content == b"selftest log: more")),
+        ("append needs the APPEND right", matches!(
+            append_without_right,
+            Err(objstore::gated::GatedError::Cap(caps::CapError::PermissionDenied)),
+        )),
+        ("revoke applies to every holder of a shared cap",
+            cap_mgr::verify(shared_revoked, Rights::READ) == Err(caps::CapError::Revoked)),
+        ("denied create is in the audit log", denied_create.is_err()
+            && matches!(
+                audit_after_denial.as_slice(),
+                [caps::audit::AuditEntry {
+                    event: caps::audit::AuditEvent::Verify,
+                    result: Err(caps::CapError::PermissionDenied),
+                    ..
+                }],
+            )
+            && audit_after_denial[0].cap_id == r_cap),
+        ("meta query finds exactly the Spanish object", obj::query_by_meta(r_cap, "lang", "es")
+            .is_ok_and(|ids| ids == [ObjId::from_content(b"hola mundo!")])),
+        ("delete drops an object from the meta index", meta_doomed.is_ok() && meta_with_doomed == 2
+            && objstore::store::query_by_meta("lang", "es").len() == 1),
+        ("update_tags ignores duplicate adds and missing removes", retag_noops.is_ok()
+            && retag_tags.as_deref().is_ok_and(|t| t == ["selftest-a", "selftest-b"])
+            && retagged.is_ok_and(|id| retag_index_a == [id])),
+        ("update_tags removal clears the tag index", retag_removed.is_ok() && retag_after.0.is_empty()),
+        ("update_tags needs WRITE", retag_read_only.is_err() && retag_after.1.is_empty()),
+        ("small inputs hash to distinct ids", small_ids.iter().enumerate()
+            .all(|(i, id)| !small_ids[i + 1..].contains(id))),
+        ("identical content hashes to the same id", small_inputs.iter().zip(&small_ids)
+            .all(|(content, id)| Object::new(content).id == *id && ObjId::from_content(content) == *id)),
+        ("different content at a taken id is a collision", planted.is_ok()
+            && colliding == Err(objstore::ObjError::HashCollision)),
+        ("identical content at a taken id already exists", duplicate == Err(objstore::ObjError::AlreadyExists)),
+        ("history walks three versions newest first", match (v1, v2, v3, doc_history) {
+            (Ok(a), Ok(b), Ok(c), Ok(Ok(h))) => h == [c, b, a],
+            _ => false,
+        }),
+        ("versions keep tags and need WRITE", read_only_version == Ok(true)
+            && doc_v3_tags.is_ok_and(|t| t == ["selftest-doc"])),
+        ("list_tags counts drop with each delete", listed_counts == [Some(2), Some(1), None]
+            && obj::list_tags(r_cap).is_ok_and(|tags| tags.contains(&(alloc::string::String::from("greeting"), 1)))),
+        ("content at MAX_OBJECT_SIZE is accepted", at_limit_created.is_ok()),
+        ("content past MAX_OBJECT_SIZE is rejected", matches!(
+            over_limit,
+            Err(objstore::gated::GatedError::Store(objstore::ObjError::TooLarge)),
+        ) && append_past_limit == Some(Err(objstore::ObjError::TooLarge))),
+        ("N+1th create evicts the least recently read", lru_survivors == [true, false]
+            && lru_c.is_ok_and(|(_, evicted)| evicted.is_some() && evicted == lru_b.ok())),
+        ("cursor allocator matches nth for 1000 frames", cursor_frames.iter().all(Option::is_some)
+            && cursor_frames == nth_frames),
+        ("bitmap allocator hands out every frame, then None", bitmap_checks[0]),
+        ("bitmap allocator reuses a freed frame", bitmap_checks[1]),
+        ("bitmap allocator reports double and foreign frees", bitmap_checks[2] && bitmap_checks[3]),
+        ("sequential reservations are disjoint", match spans {
+            [Some((a, a_size)), Some((b, b_size))] => a_size == 3 * 4096 && b_size == 2 * 4096
+                && (a + a_size <= b || b + b_size <= a),
+            _ => false,
+        }),
+        ("released frames can be reserved again", released == Some(Ok(())) && re_reserved == spans[0]
+            && double_release == Some(Err(memory::frame_allocator::FrameError::DoubleFree))),
+        ("frame stats add up", mem_stats.total_frames > 0
+            && mem_stats.used_frames + mem_stats.free_frames == mem_stats.total_frames
+            && mock_counts == (10, 1280)),
+        ("heap stats see live allocations", mem_stats.heap_used > 0 && mem_stats.heap_free > 0),
+        ("heap guard pages are unmapped", heap_guards.iter().all(|&addr| {
+            memory::heap::is_guard_page(addr) && memory::translate(x86_64::VirtAddr::new(addr as u64)).is_none()
+        })),
+        ("heap edges next to the guards are mapped", heap_edges.iter().all(|&addr| {
+            !memory::heap::is_guard_page(addr) && memory::translate(x86_64::VirtAddr::new(addr as u64)).is_some()
+        })),
+        ("scancodes for \"hi\" queue two keys in order", typed == [
+            Some(pc_keyboard::DecodedKey::Unicode('h')),
+            Some(pc_keyboard::DecodedKey::Unicode('i')),
+            None,
+        ]),
+        ("read_line stops at the line ending", line_len == 2 && line_buf[..2] == *b"hi"
+            && serial::read_byte().is_none()),
+        ("Warn level suppresses info! but not warn!", info_echo == 0 && warn_echo > 0),
+        ("fuel budget 2 rotates after two steps", fuel_run == b"aabaab"),
+        ("task sleeping 5 ticks waits for its deadline", match *sleep_log {
+            [slept, woke] => woke >= slept + 5,
+            _ => false,
+        }),
+        ("yield_now rotates without completing the step", yield_run == b"ybybyy"
+            && yield_stats.turns == 6 && yield_stats.steps == 4),
+        ("channel delivers in FIFO order", ipc_sends == [true, true]
+            && ipc_received == [Some(vec![1]), Some(vec![2]), None]),
+        ("full channel hands the message back", ipc_full == Err(vec![3])),
+        ("list shows spawned tasks", listed_before == [
+            (listed_ids.0, "sleeper", task::TaskState::Ready, 0, 2),
+            (listed_ids.1, "log-b", task::TaskState::Ready, 0, 1),
+        ]),
+        ("list tracks progress and drops finished tasks", matches!(
+            listed_after.as_slice(),
+            [(id, "sleeper", task::TaskState::Sleeping { .. }, 1, 2)] if *id == listed_ids.0,
+        )),
+        ("two 3-step tasks take 6 turns and 6 steps", counted == (6, 6, Some(3), Some(3))),
+        ("a region dominates a contained region", cap_mgr::dominates(dom_region, dom_inner) == Ok(true)
+            && cap_mgr::dominates(dom_inner, dom_region) == Ok(false)
+            && cap_mgr::dominates(dom_region, dom_overhang) == Ok(false)),
+        ("RW dominates READ over the same object only", cap_mgr::dominates(dom_rw, dom_r) == Ok(true)
+            && cap_mgr::dominates(dom_r, dom_rw) == Ok(false)
+            && cap_mgr::dominates(dom_rw, dom_other) == Ok(false)),
+        ("mint_batch returns distinct ids in spec order", batch.len() == 3
+            && batch[0] != batch[1] && batch[1] != batch[2] && batch[0] != batch[2]
+            && batch.iter().zip(&batch_specs).all(|(&id, (resource, rights, _))| {
+                cap_mgr::describe(id) == Ok((resource.clone(), *rights))
+            })),
+        ("sealed handle round-trips and hides the id", cap_mgr::unseal(sealed) == Ok(r_cap)
+            && sealed != r_cap.raw()),
+        ("tampered handle is rejected", cap_mgr::unseal(sealed ^ (1 << 63)) == Err(caps::CapError::NotFound)
+            && cap_mgr::unseal(sealed.wrapping_add(1)) == Err(caps::CapError::NotFound)),
+        ("transfer moves a WRITE cap to the consumer", handed_off),
+        ("transfer to the same task is a no-op", transfer_to_self.is_ok()),
+        ("transfer of an unheld cap fails", transfer_unheld == Err(caps::CapError::NotFound)),
+        ("copy of hello with a byte appended keeps its tags", matches!(copied, Ok(Ok(id)) if id == ObjId::from_content(b"hello!"))
+            && copied_obj.is_ok_and(|o| o.content == b"hello!" && o.tags == ["selftest-copy"])),
+        ("unchanged copy already exists", matches!(
+            copy_unchanged,
+            Ok(Err(objstore::gated::GatedError::Store(objstore::ObjError::AlreadyExists))),
+        )),
+        ("restore brings back a deleted object", snap_deleted.is_ok() && snap_count_deleted == snap_count - 1
+            && snap_count_restored == snap_count && snap_restored),
+        ("read_meta returns tags and metadata", hola_meta.is_ok_and(|m| m.id == hola_id
+            && m.tags == ["greeting", "important"]
+            && m.metadata.get("lang").is_some_and(|lang| lang == "es"))),
+        ("with_object measures content in place", hola_len.is_ok_and(|len| len == b"hola mundo!".len())),
+        ("prefix \"gre\" matches green and greeting only", match (&green, gre_tags) {
+            (Ok(green), Ok(tags)) => tags == [
+                (alloc::string::String::from("green"), vec![*green]),
+                (alloc::string::String::from("greeting"), vec![ObjId::from_content(b"hola mundo!")]),
+            ],
+            _ => false,
+        } && grz.is_ok()),
+        ("empty prefix matches every tag", all_prefixed),
+        ("a reused frame comes back zeroed", frame_zeroed),
+        ("aligned 4 MiB heap is two huge pages", huge_4m
+            == memory::heap::HugeLayout { head_pages: 0, huge_pages: 2, tail_pages: 0 }),
+        ("unaligned 4 MiB heap splits head and tail", huge_unaligned
+            == memory::heap::HugeLayout { head_pages: 511, huge_pages: 1, tail_pages: 1 }),
+        ("describe_full round-trips a quota cap", cap_mgr::describe_full(full_quota) == Ok(caps::CapInfo {
+            resource: Resource::Object(19),
+            rights: Rights::RW,
+            delegatable: true,
+            revoked: false,
+            expires_at: None,
+            remaining_uses: Some(5),
+            parent: None,
+            refcount: 1,
+        })),
+        ("describe_full reports expiry, revocation and parent",
+            cap_mgr::describe_full(full_expiring).is_ok_and(|info| info.revoked
+                && info.expires_at == Some(u64::MAX)
+                && info.rights == Rights::READ
+                && !info.delegatable)
+            && full_child.and_then(cap_mgr::describe_full).is_ok_and(|info| info.parent == Some(full_quota))),
+        ("revoke_for_resource revokes all three caps", resource_revoked == 3
+            && doomed_caps.iter().all(|&c| cap_mgr::verify(c, Rights::empty()) == Err(caps::CapError::Revoked))),
+        ("get_or_create merges tags into identical content", merged_first == Ok((hello_id, true))
+            && merged_second == Ok((hello_id, false))
+            && merged_tags.is_ok_and(|t| t == ["selftest-x", "selftest-y"])),
+        ("execute without an executor fails distinctly", matches!(
+            exec_unregistered,
+            Ok(Err(objstore::gated::GatedError::Store(objstore::ObjError::NoExecutor))),
+        )),
+        ("EXECUTE cap runs the uppercasing executor", matches!(&exec_output, Ok(Ok(out)) if out == b"SELFTEST EXEC")),
+        ("READ-only cap cannot execute", matches!(
+            exec_read_only,
+            Ok(Err(objstore::gated::GatedError::Cap(caps::CapError::PermissionDenied))),
+        )),
+        ("metadata key limit rejects one more key, not an overwrite", meta_overwrite.is_ok()
+            && matches!(meta_one_more, Err(objstore::ObjError::TooManyMetaKeys))),
+        ("metadata value limit rejects or truncates", matches!(meta_too_long, Err(objstore::ObjError::MetaValueTooLong))
+            && meta_truncated.metadata.get("k").is_some_and(|v| v.len() == objstore::MAX_META_VALUE_LEN)),
+        ("list_by_insertion keeps survivors in creation order", seq_deleted.is_ok() && match seq_ids {
+            [Ok(first), _, Ok(third)] => seq_order == [first, third],
+            _ => false,
+        }),
+        ("BCD bytes convert to binary", bcd_decoded == [0, 9, 10, 59, 99]),
+        ("hexdump of 20 bytes is two aligned lines", dumped == concat!(
+            "00000000  68 65 78 64 75 6d 70 20  73 65 6c 66 74 65 73 74  |hexdump selftest|\n",
+            "00000010  00 01 fe 7e                                       |...~|\n",
+        )),
+        ("COM2 output stays off COM1", com2_echo == 0 && com1_echo > 0),
+        ("weight 3 gets about 3x the steps of weight 1", weighted_share.1 > 0
+            && (5..=7).contains(&(2 * weighted_share.0 / weighted_share.1))),
+        ("EDF runs the nearest deadline first", edf_run == b"bbca" && edf_missed == 0),
+        ("export/import round-trips three objects", export_cleared && export_round_trip),
+        ("panic output gets past a held console lock", panic_echo > 0),
+        ("read_chunk reads the middle and clamps the tail", matches!(&hola_chunks[0], Ok(c) if c == b"mun")
+            && matches!(&hola_chunks[1], Ok(c) if c == b"mundo!")),
+        ("read_chunk at or past the end is empty", hola_chunks[2..].iter().all(|c| c.as_ref().is_ok_and(|c| c.is_empty()))),
+        ("equal resources hash equally", hash_pairs.iter().all(|(a, _)| hash_of(a) == hash_of(&a.clone()))),
+        ("different resources hash differently", hash_pairs.iter().all(|(a, b)| hash_of(a) != hash_of(b))),
+        ("kind ignores the resource's fields", hash_pairs[0].0.kind() == hash_pairs[0].1.kind()
+            && hash_pairs[1].0.kind() == caps::ResourceKind::Device
+            && hash_pairs[1].1.kind() == caps::ResourceKind::Object),
+        ("resource index matches a scan after mint/restrict/revoke", indexed_caps[0].0.len() == 2
+            && indexed_caps[1].0.len() == 1
+            && indexed_caps.iter().all(|(indexed, scanned)| indexed == scanned)),
+        ("rejected duplicate leaves no index entries", dup_first.is_ok()
+            && dup_second == Err(objstore::ObjError::AlreadyExists)
+            && dup_index == (true, true)),
+        ("10-tick CPU cap suspends its task after about 10 ticks", metered_state == Some(task::TaskState::Suspended)
+            && metered_steps.is_some_and(|steps| (8..=10).contains(&steps))),
+        ("create then delete delivers two events in order", subscribed && match watched {
+            Ok(id) => delivered == [objstore::store::StoreEvent::Created(id), objstore::store::StoreEvent::Deleted(id)],
+            Err(_) => false,
+        }),
+        ("bootloader frames stay out of the pool until reclaim", reclaim_checks[0]),
+        ("reclaim adds bootloader frames except those in use", reclaim_checks[1]),
+        ("a second reclaim adds nothing", reclaim_checks[2]),
+        ("rename_tag moves greeting to hello", matches!(renamed, Ok(1))
+            && renamed_queries.0 == [hola_id] && renamed_queries.1.is_empty()
+            && matches!(renamed_back, Ok(1))),
+        ("rename_tag to the same name is a no-op", matches!(renamed_to_self, Ok(0))),
+        ("three mutations advance the version by three", versioned.is_ok_and(|(_, v)| v == version_start + 1)
+            && version_retag.is_ok()
+            && version_delete == Ok(version_start + 3)
+            && version_end == version_start + 3),
+        ("flooding the key queue counts each dropped key", dropped_delta == 5
+            && flooded == arch::interrupts::KEY_QUEUE_CAPACITY),
+        ("double faults get their own IST stack", df_stack.is_some_and(|stack| !stack.is_empty()
+            && !stack.contains(&rsp))),
+        ("unused IST slots have no stack", arch::gdt::ist_stack(6).is_none() && arch::gdt::ist_stack(7).is_none()),
+        ("verify_integrity detects changed content", rotted == Ok(1)
+            && integrity == (Ok(false), Ok(true))
+            && corrupted.contains(&rotted_id) && !corrupted.contains(&hola_id)),
+        ("baud rates map to divisor latch values", divisors == [Some(12), Some(3), Some(1), None, None, None]
+            && serial::divisor_for(38_400) == Some(serial::DEFAULT_DIVISOR)),
+        ("cached cap verifies until revoked", fast_warm == [Ok(()), Ok(())]
+            && fast_denied == Err(caps::CapError::PermissionDenied)
+            && fast_revoked == Err(caps::CapError::Revoked)),
+        ("event loop wakes a sleeper and stops after 10 idle halts", idle_halts == 10
+            && idle_stats.idle == 10 && idle_stats.steps == 2),
+        ("content search for \"mundo\" finds only the Spanish object", mundo.is_ok_and(|ids| ids == [hola_id])),
+        ("import rejects cyclic parent links", cyclic_imports == [Err(caps::CapError::Corrupt); 2]
+            && cap_mgr::list_for_resource(&Resource::Object(23)).is_empty()),
+        ("count_by_tag follows creates and deletes", tag_counts == [Some(1), Some(2), Some(1), Some(0)]),
+        ("named cap is found by lookup", named.is_ok_and(|id| cap_mgr::lookup("selftest-console") == Some(id))
+            && cap_mgr::lookup("selftest-missing").is_none()),
+        ("reusing a cap name is rejected", renamed_mint == Err(caps::CapError::NameTaken)),
+        ("expired object reads as NotFound and is deleted", ttl_objects.iter().all(Result::is_ok)
+            && ttl_overcount == ttl_base + 4
+            && matches!(ttl_read, Ok(Err(objstore::gated::GatedError::Store(objstore::ObjError::NotFound))))
+            && ttl_after_read == ttl_base + 3),
+        ("sweep_expired deletes the other two", ttl_swept == 2 && ttl_after_sweep == ttl_base + 1),
+        ("woken sleeper queues behind waiting tasks", wake_run == b"sababsab"),
+        ("killed task never runs, the others do", kill_run && kill_gone),
+        ("kill of an unknown task is refused", !kill_again),
+        ("request_kill stops the running task", self_kill_steps == 1 && self_kill_gone),
+        ("last cap id below u64::MAX mints", last_mint == Some(CapId::from_raw(u64::MAX - 1))),
+        ("exhausted cap id space refuses to mint", exhausted_mint.is_none()),
+        ("tag count and content query skip expired objects", ttl_tag_count == 0
+            && ttl_content_hits.is_empty()
+            && ttl_tagged_gone),
+    ];
+
+    let mut passed = true;
+    for (name, ok) in checks {
+        println!("[TEST] {} ... {}", name, if ok { "ok" } else { "FAILED" });
+        passed &= ok;
+    }
+    passed
+}
+
+/// Set by `irq3_selftest`.
+#[cfg(feature = "qemu-test")]
+static IRQ3_FIRED: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// IRQ3 callback registered by `selftest`.
+#[cfg(feature = "qemu-test")]
+fn irq3_selftest() {
+    IRQ3_FIRED.store(true, core::sync::atomic::Ordering::Relaxed);
+}
+
+#[cfg(feature = "qemu-test")]
+fn idle_step(_step: u64, _caps: &[CapId]) {}
+
+/// Task names in the order `log_a`/`log_b`/`log_c` steps ran.
+#[cfg(feature = "qemu-test")]
+static TIE_BREAK_LOG: spin::Mutex<alloc::vec::Vec<u8>> = spin::Mutex::new(alloc::vec::Vec::new());
+
+#[cfg(feature = "qemu-test")]
+fn log_a(_step: u64, _caps: &[CapId]) {
+    TIE_BREAK_LOG.lock().push(b'a');
+}
+
+#[cfg(feature = "qemu-test")]
+fn log_b(_step: u64, _caps: &[CapId]) {
+    TIE_BREAK_LOG.lock().push(b'b');
+}
+
+#[cfg(feature = "qemu-test")]
+fn log_c(_step: u64, _caps: &[CapId]) {
+    TIE_BREAK_LOG.lock().push(b'c');
+}
+
+/// Run a fixed weighted task set under `policy`, with `run_edf` and equal
+/// deadlines if `edf`, and return the order its steps ran in.
+#[cfg(feature = "qemu-test")]
+fn tie_break_sequence(policy: task::scheduler::TieBreak, edf: bool) -> alloc::vec::Vec<u8> {
+    let mut sched = Scheduler::with_policy(policy);
+    let a = sched.spawn_with_weight("log-a", 3, 2, vec![], log_a);
+    let b = sched.spawn("log-b", 2, vec![], log_b);
+    if edf {
+        // Spawned second but given the same deadline: `a` still goes first
+        sched.set_deadline(b, 100);
+        sched.set_deadline(a, 100);
+        sched.run_edf();
+    } else {
+        sched.run();
+    }
+    core::mem::take(&mut *TIE_BREAK_LOG.lock())
+}
+
+/// Whether each `cap_read_step` call could read "hola mundo!".
+#[cfg(feature = "qemu-test")]
+static CAP_READS: spin::Mutex<alloc::vec::Vec<bool>> = spin::Mutex::new(alloc::vec::Vec::new());
+
+/// Try a gated read with the task's first cap, if it has one.
+#[cfg(feature = "qemu-test")]
+fn cap_read_step(_step: u64, caps: &[CapId]) {
+    let hola = objstore::ObjId::from_content(b"hola mundo!");
+    let ok = caps.first().is_some_and(|&cap| obj::read(cap, hola).is_ok());
+    CAP_READS.lock().push(ok);
+}
+
+/// Synthetic memory map for the frame allocator checks: 1280 usable
+/// frames in three regions, split by firmware and bootloader regions and
+/// an empty usable one.
+#[cfg(feature = "qemu-test")]
+static MOCK_REGIONS: [bootloader_api::info::MemoryRegion; 6] = {
+    use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+    [
+        MemoryRegion { start: 0x10_0000, end: 0x20_0000, kind: MemoryRegionKind::Usable },
+        MemoryRegion { start: 0x20_0000, end: 0x30_0000, kind: MemoryRegionKind::UnknownBios(2) },
+        MemoryRegion { start: 0x30_0000, end: 0x50_0000, kind: MemoryRegionKind::Usable },
+        MemoryRegion { start: 0x50_0000, end: 0x50_0000, kind: MemoryRegionKind::Usable },
+        MemoryRegion { start: 0x50_0000, end: 0x60_0000, kind: MemoryRegionKind::Bootloader },
+        MemoryRegion { start: 0x80_0000, end: 0xA0_0000, kind: MemoryRegionKind::Usable },
+    ]
+};
+
+/// Usable frames of `MOCK_REGIONS` the way the bump allocator used to
+/// find them, rebuilt from the memory map on every call.
+#[cfg(feature = "qemu-test")]
+fn mock_usable_frames() -> impl Iterator<Item = x86_64::structures::paging::PhysFrame> {
+    MOCK_REGIONS.iter()
+        .filter(|r| r.kind == bootloader_api::info::MemoryRegionKind::Usable)
+        .flat_map(|r| (r.start..r.end).step_by(4096))
+        .map(|addr| x86_64::structures::paging::PhysFrame::containing_address(x86_64::PhysAddr::new(addr)))
+}
+
+/// Run `f` with the console UART in loopback mode, so whatever it sends
+/// is received instead of printed. The console is COM1, as `kernel_main`
+/// calls `serial::init`.
+#[cfg(feature = "qemu-test")]
+fn with_serial_loopback<R>(f: impl FnOnce() -> R) -> R {
+    use x86_64::instructions::port::Port;
+
+    let mut modem_control = Port::<u8>::new(0x3F8 + 4);
+    // Nothing else may print while the transmitter is looped back
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let saved = unsafe { modem_control.read() };
+        unsafe { modem_control.write(saved | 0x10) };
+        let result = f();
+        unsafe { modem_control.write(saved) };
+        result
+    })
+}
+
+/// Loop `bytes` back through the console and read them with
+/// `serial::read_line`.
+#[cfg(feature = "qemu-test")]
+fn serial_loopback_line(bytes: &[u8], buf: &mut [u8]) -> usize {
+    with_serial_loopback(|| {
+        for &byte in bytes {
+            serial::serial1().lock().send_raw(byte);
+        }
+        serial::read_line(buf)
+    })
+}
+
+/// Read and discard every byte waiting on the console, returning how
+/// many there were.
+#[cfg(feature = "qemu-test")]
+fn drain_serial() -> usize {
+    core::iter::from_fn(serial::read_byte).count()
+}
+
+/// Tick at which each `sleepy_step` ran.
+#[cfg(feature = "qemu-test")]
+static SLEEP_LOG: spin::Mutex<alloc::vec::Vec<u64>> = spin::Mutex::new(alloc::vec::Vec::new());
+
+/// Sleep for 5 ticks after the first step.
+#[cfg(feature = "qemu-test")]
+fn sleepy_step(step: u64, _caps: &[CapId]) {
+    SLEEP_LOG.lock().push(arch::interrupts::ticks());
+    if step == 0 {
+        task::scheduler::sleep_current(5);
+    }
+}
+
+/// Flipped by each `yield_every_other` call.
+#[cfg(feature = "qemu-test")]
+static YIELD_FLIP: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// Log a `y`, and yield on every other call, so each step needs two turns.
+#[cfg(feature = "qemu-test")]
+fn yield_every_other(_step: u64, _caps: &[CapId]) {
+    TIE_BREAK_LOG.lock().push(b'y');
+    if !YIELD_FLIP.fetch_xor(true, core::sync::atomic::Ordering::Relaxed) {
+        task::scheduler::yield_now();
+    }
+}
+
+/// The caps each `caps_step` call was given.
+#[cfg(feature = "qemu-test")]
+static TASK_CAPS: spin::Mutex<alloc::vec::Vec<alloc::vec::Vec<CapId>>> = spin::Mutex::new(alloc::vec::Vec::new());
+
+#[cfg(feature = "qemu-test")]
+fn caps_step(_step: u64, caps: &[CapId]) {
+    TASK_CAPS.lock().push(caps.to_vec());
+}
+
+#[cfg(feature = "qemu-test")]
+fn uppercase_executor(content: &[u8]) -> alloc::vec::Vec<u8> {
+    content.to_ascii_uppercase()
+}
+
+/// FNV-1a, for checking `Hash` impls without std's hasher.
+#[cfg(feature = "qemu-test")]
+struct Fnv(u64);
+
+#[cfg(feature = "qemu-test")]
+impl core::hash::Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(feature = "qemu-test")]
+fn hash_of(value: &impl core::hash::Hash) -> u64 {
+    use core::hash::Hasher;
+
+    let mut hasher = Fnv(0xcbf2_9ce4_8422_2325);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Busy-wait into the next timer tick, so each step costs about a tick.
+#[cfg(feature = "qemu-test")]
+fn spin_one_tick(_step: u64, _caps: &[CapId]) {
+    let start = arch::interrupts::ticks();
+    while arch::interrupts::ticks() == start {
+        core::hint::spin_loop();
+    }
+}
+
+/// Store events `record_selftest_event` saw for "selftest subscribe".
+#[cfg(feature = "qemu-test")]
+static STORE_EVENTS: spin::Mutex<alloc::vec::Vec<objstore::store::StoreEvent>> =
+    spin::Mutex::new(alloc::vec::Vec::new());
+
+#[cfg(feature = "qemu-test")]
+fn record_selftest_event(event: objstore::store::StoreEvent) {
+    use objstore::store::StoreEvent;
+
+    let watched = objstore::ObjId::from_content(b"selftest subscribe");
+    let (StoreEvent::Created(id) | StoreEvent::Deleted(id) | StoreEvent::Updated(id)) = event;
+    if id == watched {
+        STORE_EVENTS.lock().push(event);
+    }
+}
+
+/// Log an `s`; on the first step, sleep for 2 ticks starting right
+/// after a tick, so `tick_a` and `tick_b` each get one turn before it
+/// wakes up.
+#[cfg(feature = "qemu-test")]
+fn nap_two_ticks(step: u64, caps: &[CapId]) {
+    TIE_BREAK_LOG.lock().push(b's');
+    if step == 0 {
+        spin_one_tick(step, caps);
+        task::scheduler::sleep_current(2);
+    }
+}
+
+#[cfg(feature = "qemu-test")]
+fn tick_a(step: u64, caps: &[CapId]) {
+    log_a(step, caps);
+    spin_one_tick(step, caps);
+}
+
+#[cfg(feature = "qemu-test")]
+fn tick_b(step: u64, caps: &[CapId]) {
+    log_b(step, caps);
+    spin_one_tick(step, caps);
+}
+
+/// Task for `kill_self_step` to kill; taken on the first step.
+#[cfg(feature = "qemu-test")]
+static KILL_TARGET: spin::Mutex<Option<task::TaskId>> = spin::Mutex::new(None);
+
+#[cfg(feature = "qemu-test")]
+fn kill_self_step(step: u64, caps: &[CapId]) {
+    caps_step(step, caps);
+    if let Some(id) = KILL_TARGET.lock().take() {
+        task::scheduler::request_kill(id);
+    }
+}
+
+/// Recurse until the kernel stack's guard page faults.
+#[cfg(feature = "stack-overflow-demo")]
+#[allow(unconditional_recursion)]
+fn stack_overflow(depth: u64) -> u64 {
+    // The volatile read keeps the call from becoming a loop
+    stack_overflow(depth + 1) + unsafe { core::ptr::read_volatile(&depth) }
 }
 
 /// Halt the CPU forever (low power).
@@ -199,8 +1704,12 @@ pub fn halt_loop() -> ! {
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     x86_64::instructions::interrupts::disable();
-    println!();
-    println!("!!! KERNEL PANIC !!!");
-    println!("{}", info);
+    // Serial may be locked by whatever panicked
+    panic_println!();
+    panic_println!("!!! KERNEL PANIC !!!");
+    panic_println!("{}", info);
+    #[cfg(feature = "qemu-test")]
+    arch::exit_qemu(arch::ExitCode::Failed);
+    #[cfg(not(feature = "qemu-test"))]
     loop { x86_64::instructions::hlt(); }
 }
@@ -1,53 +1,370 @@
-//! Physical frame allocator using the bootloader's memory map.
+//! Physical frame allocators using the bootloader's memory map.
 //!
-//! This is a simple bump allocator — frames are not freed.
-//! Sufficient for kernel heap setup; a more sophisticated allocator
-//! (bitmap or buddy) will be added in a future phase.
+//! `BootInfoFrameAllocator` is a simple bump allocator — frames are not
+//! freed. It is used to map the kernel heap, after which the remaining
+//! frames are handed to `BitmapFrameAllocator`, which supports
+//! deallocation but needs the heap for its bitmap.
 
+use alloc::vec;
+use alloc::vec::Vec;
 use bootloader_api::info::{MemoryRegionKind, MemoryRegion};
-use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
-use x86_64::PhysAddr;
+use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size2MiB, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
 
 /// A frame allocator that yields usable frames from the bootloader memory map.
+///
+/// Keeps a `(region, frame)` cursor so each allocation is O(1) rather
+/// than re-walking the memory map.
 pub struct BootInfoFrameAllocator {
     memory_regions: &'static [MemoryRegion],
-    next: usize,
+    /// Physical-memory offset used to zero frames, if zeroing is on.
+    zero_offset: Option<VirtAddr>,
+    /// Index of the region the cursor is in.
+    region: usize,
+    /// Index of the next frame within that region.
+    frame: u64,
 }
 
 impl BootInfoFrameAllocator {
     /// Create a new frame allocator from the bootloader memory map.
     ///
+    /// With `zero_offset = Some(physical_memory_offset)`, every frame is
+    /// zeroed through the physical memory mapping before it is returned.
+    /// Pass `None` to skip zeroing (e.g. for heap setup).
+    ///
     /// # Safety
     /// The caller must guarantee that the memory map is valid and that
-    /// all `Usable` regions are truly unused.
-    pub unsafe fn new(memory_regions: &'static [MemoryRegion]) -> Self {
+    /// all `Usable` regions are truly unused. If given, `zero_offset` must
+    /// be the offset at which all physical memory is mapped writable.
+    pub unsafe fn new(memory_regions: &'static [MemoryRegion], zero_offset: Option<VirtAddr>) -> Self {
         BootInfoFrameAllocator {
             memory_regions,
-            next: 0,
+            zero_offset,
+            region: 0,
+            frame: 0,
         }
     }
+}
 
-    /// Returns an iterator over all usable physical frames.
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> + '_ {
+impl BootInfoFrameAllocator {
+    /// Number of frames handed out so far.
+    fn allocated(&self) -> u64 {
         self.memory_regions
             .iter()
-            .filter(|r| r.kind == MemoryRegionKind::Usable)
-            .flat_map(|r| {
-                let start = r.start;
-                let end = r.end;
-                let frame_count = (end - start) / 4096;
-                (0..frame_count).map(move |i| {
-                    let addr = PhysAddr::new(start + i * 4096);
-                    PhysFrame::containing_address(addr)
-                })
+            .take(self.region + 1)
+            .enumerate()
+            .filter(|(_, r)| r.kind == MemoryRegionKind::Usable)
+            .map(|(i, r)| {
+                let frame_count = (r.end - r.start) / 4096;
+                if i < self.region { frame_count } else { self.frame.min(frame_count) }
             })
+            .sum()
     }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        // Skips non-usable and exhausted regions; amortized O(1)
+        while let Some(r) = self.memory_regions.get(self.region) {
+            let frame_count = (r.end - r.start) / 4096;
+            if r.kind == MemoryRegionKind::Usable && self.frame < frame_count {
+                let addr = PhysAddr::new(r.start + self.frame * 4096);
+                self.frame += 1;
+                let frame = PhysFrame::containing_address(addr);
+                if let Some(offset) = self.zero_offset {
+                    unsafe { zero_frame(offset, frame) };
+                }
+                return Some(frame);
+            }
+            self.region += 1;
+            self.frame = 0;
+        }
+        None
+    }
+}
+
+/// 2 MiB frames for huge-page mappings.
+///
+/// Advances the cursor to the next 2 MiB-aligned address in a usable
+/// region with room for a whole huge frame; the 4 KiB frames skipped to
+/// reach alignment are never handed out.
+unsafe impl FrameAllocator<Size2MiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        const HUGE: u64 = 2 * 1024 * 1024;
+        while let Some(r) = self.memory_regions.get(self.region) {
+            if r.kind == MemoryRegionKind::Usable {
+                let aligned = (r.start + self.frame * 4096).next_multiple_of(HUGE);
+                if aligned + HUGE <= r.end {
+                    self.frame = (aligned + HUGE - r.start) / 4096;
+                    let frame = PhysFrame::containing_address(PhysAddr::new(aligned));
+                    if let Some(offset) = self.zero_offset {
+                        let first = PhysFrame::<Size4KiB>::containing_address(frame.start_address());
+                        for i in 0..HUGE / 4096 {
+                            unsafe { zero_frame(offset, first + i) };
+                        }
+                    }
+                    return Some(frame);
+                }
+            }
+            self.region += 1;
+            self.frame = 0;
+        }
+        None
     }
 }
+
+// ─── Bitmap allocator ───────────────────────────────────────────
+
+/// Frame deallocation errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// Frame is not part of a usable region of the memory map.
+    NotManaged,
+    /// Frame is already free.
+    DoubleFree,
+}
+
+impl core::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            FrameError::NotManaged => write!(f, "frame not managed"),
+            FrameError::DoubleFree => write!(f, "double free"),
+        }
+    }
+}
+
+/// A frame allocator with one bit per managed frame (set = allocated).
+///
+/// Usable frames are numbered in memory-map order, matching the order in
+/// which `BootInfoFrameAllocator` hands them out; bootloader regions added
+/// by `reclaim` are numbered after them. The region list is copied out of
+/// the memory map, which itself lives in reclaimable memory.
+pub struct BitmapFrameAllocator {
+    /// `(start, frame_count)` of each managed region, in index order.
+    regions: Vec<(u64, u64)>,
+    /// Bootloader regions not yet handed over by `reclaim`.
+    reclaimable: Vec<(u64, u64)>,
+    /// Physical-memory offset used to zero frames, if zeroing is on.
+    zero_offset: Option<VirtAddr>,
+    bitmap: Vec<u64>,
+    total: u64,
+    /// Lowest index that might be free; speeds up sequential allocation.
+    hint: u64,
+}
+
+impl BitmapFrameAllocator {
+    /// Build a bitmap allocator that takes over from a bump allocator.
+    ///
+    /// Every frame the bump allocator already handed out is marked used.
+    /// The zeroing mode is inherited from the bump allocator; see
+    /// `with_zeroing` to change it.
+    pub fn from_bump(bump: BootInfoFrameAllocator) -> Self {
+        let mut alloc = Self::new(bump.memory_regions);
+        alloc.zero_offset = bump.zero_offset;
+        for index in 0..bump.allocated() {
+            alloc.set(index);
+        }
+        alloc.hint = bump.allocated();
+        alloc
+    }
+
+    /// Build a bitmap allocator with every usable frame free.
+    ///
+    /// The memory map must be valid, as for `BootInfoFrameAllocator::new`.
+    pub fn new(memory_regions: &[MemoryRegion]) -> Self {
+        let regions: Vec<(u64, u64)> = usable_regions(memory_regions).collect();
+        let total = regions.iter().map(|(_, count)| count).sum::<u64>();
+        BitmapFrameAllocator {
+            regions,
+            reclaimable: regions_of(memory_regions, MemoryRegionKind::Bootloader).collect(),
+            zero_offset: None,
+            bitmap: vec![0; total.div_ceil(64) as usize],
+            total,
+            hint: 0,
+        }
+    }
+
+    /// Zero every frame before handing it out (including contiguous runs)
+    /// through the physical memory mapping at `phys_mem_offset`.
+    ///
+    /// # Safety
+    /// `phys_mem_offset` must be the offset at which all physical memory
+    /// is mapped writable.
+    pub unsafe fn with_zeroing(mut self, phys_mem_offset: VirtAddr) -> Self {
+        self.zero_offset = Some(phys_mem_offset);
+        self
+    }
+
+    /// Add the bootloader's regions to the pool, once nothing the
+    /// bootloader set up is needed any more. Frames for which `in_use`
+    /// returns true are added as allocated, so they are never handed out.
+    /// Returns how many frames became free. Later calls add nothing.
+    ///
+    /// # Safety
+    /// `in_use` must report every bootloader frame still referenced —
+    /// page tables, the kernel stack, and anything still read from
+    /// `BootInfo` (including the memory map).
+    pub unsafe fn reclaim(&mut self, in_use: impl Fn(PhysFrame) -> bool) -> u64 {
+        let mut freed = 0;
+        for (start, count) in core::mem::take(&mut self.reclaimable) {
+            let base = self.total;
+            self.regions.push((start, count));
+            self.total += count;
+            self.bitmap.resize(self.total.div_ceil(64) as usize, 0);
+            for i in 0..count {
+                let frame = PhysFrame::containing_address(PhysAddr::new(start + i * 4096));
+                if in_use(frame) {
+                    self.set(base + i);
+                } else {
+                    freed += 1;
+                }
+            }
+        }
+        self.hint = self.hint.min(self.total);
+        freed
+    }
+
+    /// Return a frame to the free pool.
+    pub fn deallocate_frame(&mut self, frame: PhysFrame) -> Result<(), FrameError> {
+        let index = self.index_of(frame).ok_or(FrameError::NotManaged)?;
+        if !self.is_set(index) {
+            return Err(FrameError::DoubleFree);
+        }
+        self.bitmap[(index / 64) as usize] &= !(1 << (index % 64));
+        self.hint = self.hint.min(index);
+        Ok(())
+    }
+
+    /// Allocate `count` physically contiguous frames, returning the first.
+    ///
+    /// A run never spans two memory-map regions, so it is contiguous in
+    /// physical memory as well as in the bitmap.
+    pub fn allocate_contiguous(&mut self, count: u64) -> Option<PhysFrame> {
+        if count == 0 {
+            return None;
+        }
+        let mut base = 0;
+        for r in 0..self.regions.len() {
+            let (_, frames) = self.regions[r];
+            let mut run = 0;
+            for i in base..base + frames {
+                run = if self.is_set(i) { 0 } else { run + 1 };
+                if run == count {
+                    let first = i + 1 - count;
+                    for j in first..=i {
+                        self.set(j);
+                    }
+                    let start = self.frame_at(first)?;
+                    if let Some(offset) = self.zero_offset {
+                        for k in 0..count {
+                            unsafe { zero_frame(offset, start + k) };
+                        }
+                    }
+                    return Some(start);
+                }
+            }
+            base += frames;
+        }
+        None
+    }
+
+    /// Free `count` contiguous frames starting at `first`.
+    ///
+    /// Fails without freeing anything if any frame in the range is
+    /// unmanaged or already free.
+    pub fn deallocate_contiguous(&mut self, first: PhysFrame, count: u64) -> Result<(), FrameError> {
+        let start = self.index_of(first).ok_or(FrameError::NotManaged)?;
+        for i in 0..count {
+            let frame = first + i;
+            if self.index_of(frame) != Some(start + i) {
+                return Err(FrameError::NotManaged);
+            }
+            if !self.is_set(start + i) {
+                return Err(FrameError::DoubleFree);
+            }
+        }
+        for i in 0..count {
+            self.bitmap[((start + i) / 64) as usize] &= !(1 << ((start + i) % 64));
+        }
+        self.hint = self.hint.min(start);
+        Ok(())
+    }
+
+    /// Total number of usable frames managed.
+    pub fn total_frames(&self) -> u64 {
+        self.total
+    }
+
+    /// Number of frames currently allocated.
+    pub fn used_frames(&self) -> u64 {
+        self.bitmap.iter().map(|w| w.count_ones() as u64).sum()
+    }
+
+    fn is_set(&self, index: u64) -> bool {
+        self.bitmap[(index / 64) as usize] & (1 << (index % 64)) != 0
+    }
+
+    fn set(&mut self, index: u64) {
+        self.bitmap[(index / 64) as usize] |= 1 << (index % 64);
+    }
+
+    /// Physical frame for a bitmap index.
+    fn frame_at(&self, mut index: u64) -> Option<PhysFrame> {
+        for &(start, count) in &self.regions {
+            if index < count {
+                return Some(PhysFrame::containing_address(PhysAddr::new(start + index * 4096)));
+            }
+            index -= count;
+        }
+        None
+    }
+
+    /// Bitmap index for a physical frame.
+    fn index_of(&self, frame: PhysFrame) -> Option<u64> {
+        let addr = frame.start_address().as_u64();
+        let mut base = 0;
+        for &(start, count) in &self.regions {
+            if addr >= start && addr < start + count * 4096 {
+                return Some(base + (addr - start) / 4096);
+            }
+            base += count;
+        }
+        None
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        let index = (self.hint..self.total).find(|&i| !self.is_set(i))?;
+        self.set(index);
+        self.hint = index + 1;
+        let frame = self.frame_at(index)?;
+        if let Some(offset) = self.zero_offset {
+            unsafe { zero_frame(offset, frame) };
+        }
+        Some(frame)
+    }
+}
+
+/// Fill a frame with zeros through the physical memory mapping.
+///
+/// # Safety
+/// `offset` must map all physical memory writable, and nothing may be
+/// using `frame`.
+unsafe fn zero_frame(offset: VirtAddr, frame: PhysFrame) {
+    let ptr: *mut u8 = (offset + frame.start_address().as_u64()).as_mut_ptr();
+    unsafe { core::ptr::write_bytes(ptr, 0, 4096) };
+}
+
+/// `(start, frame_count)` of each usable region, in memory-map order.
+fn usable_regions(regions: &[MemoryRegion]) -> impl Iterator<Item = (u64, u64)> + '_ {
+    regions_of(regions, MemoryRegionKind::Usable)
+}
+
+/// `(start, frame_count)` of each region of `kind`, in memory-map order.
+fn regions_of(regions: &[MemoryRegion], kind: MemoryRegionKind) -> impl Iterator<Item = (u64, u64)> + '_ {
+    regions
+        .iter()
+        .filter(move |r| r.kind == kind)
+        .map(|r| (r.start, (r.end - r.start) / 4096))
+}
@@ -2,10 +2,17 @@
 //!
 //! Maps virtual pages to physical frames and registers a
 //! linked_list_allocator as the #[global_allocator].
+//!
+//! The usable heap spans `[HEAP_START, HEAP_START + HEAP_SIZE)`. The page
+//! directly below `HEAP_START` and the page directly after the current
+//! heap end are guard pages: kept unmapped so that an overrun page-faults
+//! instead of silently corrupting neighbouring memory.
 
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use linked_list_allocator::LockedHeap;
 use x86_64::structures::paging::{
-    mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+    mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size2MiB, Size4KiB,
 };
 use x86_64::VirtAddr;
 
@@ -15,6 +22,12 @@ pub const HEAP_START: usize = 0x_4444_4444_0000;
 /// Size of the kernel heap in bytes (100 KiB).
 pub const HEAP_SIZE: usize = 100 * 1024;
 
+/// Size of each guard page around the heap.
+pub const GUARD_PAGE_SIZE: usize = 4096;
+
+/// Current end of the mapped heap (exclusive); advanced by `grow`.
+static HEAP_END: AtomicUsize = AtomicUsize::new(HEAP_START + HEAP_SIZE);
+
 /// The global heap allocator.
 #[global_allocator]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
@@ -22,11 +35,14 @@ static ALLOCATOR: LockedHeap = LockedHeap::empty();
 /// Initialize the kernel heap.
 ///
 /// Maps `HEAP_SIZE` bytes of virtual memory starting at `HEAP_START`
-/// to physical frames, then initializes the linked list allocator.
+/// to physical frames, ensures the guard pages on either side are
+/// unmapped, then initializes the linked list allocator.
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
+    unmap_guards(mapper);
+
     let page_range = {
         let heap_start = VirtAddr::new(HEAP_START as u64);
         let heap_end = heap_start + HEAP_SIZE as u64 - 1u64;
@@ -53,3 +69,216 @@ pub fn init_heap(
 
     Ok(())
 }
+
+/// How a heap span splits into 4 KiB and 2 MiB pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HugeLayout {
+    /// 4 KiB pages before the first 2 MiB boundary.
+    pub head_pages: u64,
+    /// Whole 2 MiB pages in between.
+    pub huge_pages: u64,
+    /// 4 KiB pages after the last whole 2 MiB page.
+    pub tail_pages: u64,
+}
+
+const HUGE_PAGE_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Split `[start, start + size)` (4 KiB-aligned) into a 4 KiB head up to
+/// the first 2 MiB boundary, as many 2 MiB pages as fit, and a 4 KiB tail.
+pub fn huge_layout(start: usize, size: usize) -> HugeLayout {
+    let (start, size) = (start as u64, size.div_ceil(4096) as u64 * 4096);
+    let head = (start.next_multiple_of(HUGE_PAGE_SIZE) - start).min(size);
+    let huge = (size - head) / HUGE_PAGE_SIZE;
+    let tail = size - head - huge * HUGE_PAGE_SIZE;
+    HugeLayout {
+        head_pages: head / 4096,
+        huge_pages: huge,
+        tail_pages: tail / 4096,
+    }
+}
+
+/// Initialize the kernel heap using 2 MiB pages where possible.
+///
+/// Like `init_heap`, but the part of the heap between the first and last
+/// 2 MiB boundaries is mapped with huge pages; an unaligned head and any
+/// remainder fall back to 4 KiB pages (see `huge_layout`). With the
+/// default 100 KiB heap this maps only 4 KiB pages.
+pub fn init_heap_huge<M, A>(mapper: &mut M, frame_allocator: &mut A) -> Result<(), MapToError<Size4KiB>>
+where
+    M: Mapper<Size4KiB> + Mapper<Size2MiB>,
+    A: FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>,
+{
+    unmap_guards(mapper);
+
+    let layout = huge_layout(HEAP_START, HEAP_SIZE);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let mut addr = HEAP_START as u64;
+
+    for _ in 0..layout.head_pages {
+        map_small(mapper, frame_allocator, addr, flags)?;
+        addr += 4096;
+    }
+    for _ in 0..layout.huge_pages {
+        let page = Page::<Size2MiB>::containing_address(VirtAddr::new(addr));
+        let frame: PhysFrame<Size2MiB> = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        unsafe {
+            match Mapper::<Size2MiB>::map_to(mapper, page, frame, flags, frame_allocator) {
+                Ok(flush) => flush.flush(),
+                Err(e) => return Err(small_error(e)),
+            }
+        }
+        addr += HUGE_PAGE_SIZE;
+    }
+    for _ in 0..layout.tail_pages {
+        map_small(mapper, frame_allocator, addr, flags)?;
+        addr += 4096;
+    }
+
+    unsafe {
+        ALLOCATOR.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
+    }
+    Ok(())
+}
+
+/// Map the single 4 KiB page at `addr` to a fresh frame.
+fn map_small<M, A>(mapper: &mut M, frame_allocator: &mut A, addr: u64, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>>
+where
+    M: Mapper<Size4KiB>,
+    A: FrameAllocator<Size4KiB>,
+{
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+    let frame: PhysFrame<Size4KiB> = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    unsafe {
+        mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    }
+    Ok(())
+}
+
+/// Report a 2 MiB mapping failure in terms of the 4 KiB error type.
+fn small_error(e: MapToError<Size2MiB>) -> MapToError<Size4KiB> {
+    match e {
+        MapToError::FrameAllocationFailed => MapToError::FrameAllocationFailed,
+        MapToError::ParentEntryHugePage => MapToError::ParentEntryHugePage,
+        MapToError::PageAlreadyMapped(frame) => {
+            MapToError::PageAlreadyMapped(PhysFrame::containing_address(frame.start_address()))
+        }
+    }
+}
+
+/// Make sure the guard pages on either side of the initial heap are unmapped.
+fn unmap_guards(mapper: &mut impl Mapper<Size4KiB>) {
+    let below = Page::<Size4KiB>::containing_address(VirtAddr::new((HEAP_START - GUARD_PAGE_SIZE) as u64));
+    let above = Page::<Size4KiB>::containing_address(VirtAddr::new((HEAP_START + HEAP_SIZE) as u64));
+    for guard in [below, above] {
+        if let Ok((_, flush)) = mapper.unmap(guard) {
+            flush.flush();
+        }
+    }
+}
+
+/// Grow the heap by at least `additional` bytes (rounded up to whole pages).
+///
+/// Maps fresh pages directly after the current heap end and extends the
+/// allocator over them, so repeated growth stays contiguous. The upper
+/// guard page becomes heap; the page after the new end is left unmapped. If frames run
+/// out part-way, the pages mapped so far are kept and the error returned.
+pub fn grow(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    additional: usize,
+) -> Result<(), MapToError<Size4KiB>> {
+    let start = HEAP_END.load(Ordering::Relaxed);
+    let pages = additional.div_ceil(4096);
+    let first_page = Page::<Size4KiB>::containing_address(VirtAddr::new(start as u64));
+
+    let mut mapped = 0;
+    let mut result = Ok(());
+    for page in (0..pages as u64).map(|i| first_page + i) {
+        let frame = match frame_allocator.allocate_frame() {
+            Some(frame) => frame,
+            None => {
+                result = Err(MapToError::FrameAllocationFailed);
+                break;
+            }
+        };
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => flush.flush(),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+        mapped += 1;
+    }
+
+    if mapped > 0 {
+        let by = mapped * 4096;
+        // Safety: the `by` bytes after the current top were just mapped
+        unsafe {
+            ALLOCATOR.lock().extend(by);
+        }
+        HEAP_END.store(start + by, Ordering::Relaxed);
+    }
+    result
+}
+
+/// Current end of the mapped heap (exclusive).
+pub fn end() -> usize {
+    HEAP_END.load(Ordering::Relaxed)
+}
+
+/// Whether `addr` falls in one of the heap's guard pages.
+pub fn is_guard_page(addr: usize) -> bool {
+    let end = HEAP_END.load(Ordering::Relaxed);
+    (HEAP_START - GUARD_PAGE_SIZE..HEAP_START).contains(&addr)
+        || (end..end + GUARD_PAGE_SIZE).contains(&addr)
+}
+
+/// Called when the heap can't satisfy an allocation: report the failed
+/// `Layout` and what memory is left, then halt.
+///
+/// Locks are only tried, never waited on — the failing allocation may
+/// have come from code already holding one of them.
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    x86_64::instructions::interrupts::disable();
+    crate::panic_println!();
+    crate::panic_println!("!!! OUT OF MEMORY !!!");
+    crate::panic_println!("Failed allocation: {} bytes, align {}", layout.size(), layout.align());
+    match ALLOCATOR.try_lock() {
+        Some(heap) => crate::panic_println!(
+            "Heap: {} used, {} free of {} bytes",
+            heap.used(),
+            heap.free(),
+            heap.size()
+        ),
+        None => crate::panic_println!("Heap: (locked)"),
+    }
+    match super::FRAME_ALLOCATOR.try_lock().as_ref().map(|a| a.as_ref()) {
+        Some(Some(frames)) => crate::panic_println!(
+            "Frames: {}/{} used",
+            frames.used_frames(),
+            frames.total_frames()
+        ),
+        Some(None) => {}
+        None => crate::panic_println!("Frames: (locked)"),
+    }
+    // The diagnostic is all the OOM demo has to show
+    if cfg!(all(feature = "oom-demo", feature = "qemu-test")) {
+        crate::arch::exit_qemu(crate::arch::ExitCode::Success);
+    }
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Bytes of the kernel heap currently allocated and free, as `(used, free)`.
+pub fn usage() -> (usize, usize) {
+    let heap = ALLOCATOR.lock();
+    (heap.used(), heap.free())
+}
@@ -3,15 +3,55 @@
 //! Provides:
 //! - Physical frame allocation from bootloader memory map
 //! - Kernel heap via linked_list_allocator
+//! - A global bitmap frame allocator (with deallocation) once the heap exists
+//! - Contiguous physical reservations backing `Resource::Memory` caps
 
 pub mod frame_allocator;
 pub mod heap;
 
+use alloc::vec::Vec;
 use bootloader_api::BootInfo;
-use x86_64::structures::paging::{OffsetPageTable, PageTable};
-use x86_64::VirtAddr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{OffsetPageTable, PageTable, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
 
+use crate::caps::Resource;
 use crate::println;
+use frame_allocator::{BitmapFrameAllocator, FrameError};
+
+/// Snapshot of physical frame and kernel heap usage.
+#[derive(Debug, Clone, Copy)]
+pub struct MemStats {
+    pub total_frames: u64,
+    pub used_frames: u64,
+    pub free_frames: u64,
+    pub heap_used: usize,
+    pub heap_free: usize,
+}
+
+impl core::fmt::Display for MemStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "frames {}/{} used, heap {}/{} KiB used",
+            self.used_frames,
+            self.total_frames,
+            self.heap_used / 1024,
+            (self.heap_used + self.heap_free) / 1024,
+        )
+    }
+}
+
+/// Frame allocator for the rest of the kernel's lifetime; set by `init`.
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+/// Virtual offset of the physical memory mapping; set by `init`.
+static PHYS_MEM_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// End of the highest memory-map region, bounding that mapping; set by `init`.
+static PHYS_MEM_END: AtomicU64 = AtomicU64::new(0);
 
 /// Initialize all memory subsystems.
 ///
@@ -22,14 +62,18 @@ pub fn init(boot_info: &'static BootInfo) {
         .into_option()
         .expect("bootloader must map physical memory");
     let phys_mem_offset = VirtAddr::new(phys_mem_offset);
+    PHYS_MEM_OFFSET.store(phys_mem_offset.as_u64(), Ordering::Relaxed);
+    let phys_end = boot_info.memory_regions.iter().map(|r| r.end).max().unwrap_or(0);
+    PHYS_MEM_END.store(phys_end, Ordering::Relaxed);
 
     // Set up page table mapper
     let level_4_table = unsafe { active_level_4_table(phys_mem_offset) };
     let mut mapper = unsafe { OffsetPageTable::new(level_4_table, phys_mem_offset) };
 
     // Initialize frame allocator from bootloader memory map
+    // Heap setup skips zeroing for speed; the heap never exposes raw frames
     let mut frame_allocator =
-        unsafe { frame_allocator::BootInfoFrameAllocator::new(&boot_info.memory_regions) };
+        unsafe { frame_allocator::BootInfoFrameAllocator::new(&boot_info.memory_regions, None) };
 
     let usable_frames = boot_info
         .memory_regions
@@ -46,6 +90,181 @@ pub fn init(boot_info: &'static BootInfo) {
     heap::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap initialization failed");
     println!("[OK] Kernel heap initialized ({} KiB)", heap::HEAP_SIZE / 1024);
+
+    // Hand the remaining frames to the bitmap allocator. Those may back
+    // capability-protected memory, so they are zeroed before reuse.
+    let bitmap = unsafe { BitmapFrameAllocator::from_bump(frame_allocator).with_zeroing(phys_mem_offset) };
+    *FRAME_ALLOCATOR.lock() = Some(bitmap);
+    println!("[OK] Bitmap frame allocator active");
+    println!("[OK] Memory: {}", stats());
+}
+
+/// Current memory usage. Frame counts are zero before `init` completes.
+pub fn stats() -> MemStats {
+    let (total_frames, used_frames) = FRAME_ALLOCATOR
+        .lock()
+        .as_ref()
+        .map_or((0, 0), |a| (a.total_frames(), a.used_frames()));
+    let (heap_used, heap_free) = heap::usage();
+    MemStats {
+        total_frames,
+        used_frames,
+        free_frames: total_frames - used_frames,
+        heap_used,
+        heap_free,
+    }
+}
+
+/// Grow the kernel heap by at least `additional` bytes, mapping the new
+/// pages with frames from `FRAME_ALLOCATOR`. See `heap::grow`.
+pub fn grow_heap(additional: usize) -> Result<(), MapToError<Size4KiB>> {
+    let offset = VirtAddr::new(PHYS_MEM_OFFSET.load(Ordering::Relaxed));
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frames = guard.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    // Safety: `init`'s mapper is gone, and the frame allocator lock keeps
+    // other growers from holding a second mapper at the same time
+    let mut mapper = unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) };
+    heap::grow(&mut mapper, frames, additional)
+}
+
+/// Reserve `size` bytes (rounded up to whole frames) of contiguous
+/// physical memory, returning it as a resource a capability can be
+/// minted over. Reservations never overlap until released.
+pub fn reserve(size: u64) -> Option<Resource> {
+    let frames = size.div_ceil(4096);
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let first = guard.as_mut()?.allocate_contiguous(frames)?;
+    Some(Resource::Memory {
+        base: first.start_address().as_u64(),
+        size: frames * 4096,
+    })
+}
+
+/// Return a region obtained from `reserve` to the frame allocator.
+pub fn release(base: u64, size: u64) -> Result<(), FrameError> {
+    let first = PhysFrame::from_start_address(PhysAddr::new(base))
+        .map_err(|_| FrameError::NotManaged)?;
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let alloc = guard.as_mut().ok_or(FrameError::NotManaged)?;
+    alloc.deallocate_contiguous(first, size.div_ceil(4096))
+}
+
+/// Hand the bootloader's memory regions to the frame allocator, keeping
+/// back every frame the active page tables still use: the tables
+/// themselves and everything they map outside the physical memory window
+/// (kernel image and stack, boot info, memory map, framebuffer). Returns
+/// how many frames were added to the free pool; later calls return 0.
+///
+/// # Safety
+/// After this call `BootInfo` must only be accessed through memory that
+/// is still mapped; in particular, nothing may rely on the contents of
+/// bootloader memory that is not mapped into the kernel's address space.
+pub unsafe fn reclaim_bootloader_regions() -> u64 {
+    let offset = VirtAddr::new(PHYS_MEM_OFFSET.load(Ordering::Relaxed));
+    let in_use = unsafe { pinned_ranges(offset, PHYS_MEM_END.load(Ordering::Relaxed)) };
+    let pinned = |frame: PhysFrame| {
+        let addr = frame.start_address().as_u64();
+        let i = in_use.partition_point(|&(start, _)| start <= addr);
+        i > 0 && addr < in_use[i - 1].1
+    };
+
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let freed = match guard.as_mut() {
+        Some(alloc) => unsafe { alloc.reclaim(pinned) },
+        None => 0,
+    };
+    drop(guard);
+    println!("[OK] Reclaimed {} bootloader frames ({} KiB)", freed, freed * 4);
+    freed
+}
+
+/// Physical `(start, end)` ranges referenced by the active page tables,
+/// sorted and merged: every table frame, plus every mapped frame except
+/// those reached through the physical memory window at `offset`.
+///
+/// # Safety
+/// `offset` must map all physical memory up to `phys_end`.
+unsafe fn pinned_ranges(offset: VirtAddr, phys_end: u64) -> Vec<(u64, u64)> {
+    use x86_64::registers::control::Cr3;
+
+    let window = offset.as_u64()..offset.as_u64().saturating_add(phys_end);
+    let mut ranges = Vec::new();
+    let p4 = Cr3::read().0.start_address().as_u64();
+    ranges.push((p4, p4 + 4096));
+    unsafe { walk_table(offset, p4, 4, 0, &window, &mut ranges) };
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Record the frames referenced by one page table at `level` (4 = PML4)
+/// covering virtual addresses from `virt_base`.
+unsafe fn walk_table(
+    offset: VirtAddr,
+    table_phys: u64,
+    level: u32,
+    virt_base: u64,
+    window: &core::ops::Range<u64>,
+    ranges: &mut Vec<(u64, u64)>,
+) {
+    let table: &PageTable = unsafe { &*(offset + table_phys).as_ptr() };
+    let span = 4096u64 << (9 * (level - 1));
+    for (i, entry) in table.iter().enumerate() {
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let mut virt = virt_base + i as u64 * span;
+        if level == 4 && i >= 256 {
+            // Sign-extend into the higher half
+            virt |= 0xFFFF_0000_0000_0000;
+        }
+        let phys = entry.addr().as_u64();
+        let leaf = level == 1 || entry.flags().contains(PageTableFlags::HUGE_PAGE);
+        if leaf {
+            if !window.contains(&virt) {
+                ranges.push((phys, phys + span));
+            }
+        } else {
+            ranges.push((phys, phys + 4096));
+            unsafe { walk_table(offset, phys, level - 1, virt, window, ranges) };
+        }
+    }
+}
+
+/// Virtual address at which the bootloader's physical memory mapping
+/// makes `phys` accessible. Only meaningful after `init`.
+pub fn phys_to_virt(phys: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYS_MEM_OFFSET.load(Ordering::Relaxed) + phys.as_u64())
+}
+
+/// Physical address `virt` maps to in the active page tables, or None if
+/// it is unmapped. Handles 2 MiB and 1 GiB pages. Only valid after `init`.
+pub fn translate(virt: VirtAddr) -> Option<PhysAddr> {
+    use x86_64::registers::control::Cr3;
+
+    let mut table_phys = Cr3::read().0.start_address();
+    let indices = [virt.p4_index(), virt.p3_index(), virt.p2_index(), virt.p1_index()];
+    for (level, index) in (1..=4u32).rev().zip(indices) {
+        // Read-only view through the physical memory mapping
+        let table: &PageTable = unsafe { &*phys_to_virt(table_phys).as_ptr() };
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        if level == 1 || (level < 4 && entry.flags().contains(PageTableFlags::HUGE_PAGE)) {
+            let span = 4096u64 << (9 * (level - 1));
+            return Some(entry.addr() + (virt.as_u64() & (span - 1)));
+        }
+        table_phys = entry.addr();
+    }
+    None
 }
 
 /// Get a mutable reference to the active level 4 page table.
@@ -53,7 +272,8 @@ pub fn init(boot_info: &'static BootInfo) {
 /// # Safety
 /// - `physical_memory_offset` must be the correct offset that the bootloader
 ///   used to map all physical memory.
-/// - Must only be called once to avoid aliasing `&mut` references.
+/// - The returned reference must not outlive any other one handed out,
+///   to avoid aliasing `&mut` references.
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
     use x86_64::registers::control::Cr3;
 
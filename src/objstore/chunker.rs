@@ -0,0 +1,65 @@
+//! Content-defined chunking with a gear rolling hash.
+//!
+//! Boundaries depend only on the bytes just before them, so an edit early
+//! in a buffer shifts at most the chunks around it and the rest still
+//! line up with (and dedup against) the unedited version.
+
+use alloc::vec::Vec;
+
+/// No chunk is cut shorter than this, except the last.
+pub const MIN_CHUNK: usize = 512;
+
+/// A chunk is cut here even if no boundary was found.
+pub const MAX_CHUNK: usize = 8 * 1024;
+
+/// A boundary falls where the hash's low bits are all zero, giving
+/// chunks of roughly `MIN_CHUNK + 2 KiB` on average.
+const BOUNDARY_MASK: u64 = (1 << 11) - 1;
+
+/// Per-byte gear values, filled from SplitMix64 so they are fixed across
+/// boots. Changing them changes every chunk boundary.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x6368_756e_6b65_7273;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Split `data` into consecutive chunks, each `MIN_CHUNK..=MAX_CHUNK`
+/// bytes long apart from a shorter final chunk. Empty input yields no
+/// chunks.
+pub fn split(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let len = boundary(rest);
+        chunks.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+    chunks
+}
+
+/// Length of the chunk starting at `data[0]`.
+fn boundary(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK);
+    let mut hash: u64 = 0;
+    for (i, &byte) in data[..end].iter().enumerate() {
+        // Old bytes shift out after 64 steps, so the hash only sees a window
+        hash = (hash << 1).wrapping_add(GEAR[usize::from(byte)]);
+        if i + 1 >= MIN_CHUNK && hash & BOUNDARY_MASK == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
@@ -1,11 +1,13 @@
 //! Capability-gated Object Store access.
 //!
 //! Wraps raw store operations with capability checks.
-//! WRITE cap required to create, READ to read/query, DELETE to delete.
+//! WRITE cap required to create, READ to read/query, DELETE to delete,
+//! APPEND to append, EXECUTE to execute.
 
+use alloc::string::String;
 use alloc::vec::Vec;
-use super::{ObjId, Object, ObjError, store};
-use crate::caps::{self, CapId, Rights, CapError};
+use super::{ObjId, Object, ObjectMeta, ObjError, store};
+use crate::caps::{self, CapId, Rights, CapError, Resource};
 
 /// Error from a gated store operation.
 #[derive(Debug)]
@@ -33,7 +35,32 @@ impl From<ObjError> for GatedError {
 /// Create an object (requires WRITE cap).
 pub fn create(cap_id: CapId, obj: Object) -> Result<ObjId, GatedError> {
     caps::manager::verify(cap_id, Rights::WRITE)?;
-    Ok(store::create(obj)?)
+    Ok(store::create(obj)?.0)
+}
+
+/// Create an object or merge into an identical one (requires WRITE cap).
+/// See `store::get_or_create`.
+pub fn get_or_create(cap_id: CapId, obj: Object) -> Result<(ObjId, bool), GatedError> {
+    caps::manager::verify(cap_id, Rights::WRITE)?;
+    Ok(store::get_or_create(obj)?)
+}
+
+/// Create a new version of an object (requires WRITE cap).
+pub fn create_version(cap_id: CapId, prev: ObjId, new_content: &[u8]) -> Result<ObjId, GatedError> {
+    caps::manager::verify(cap_id, Rights::WRITE)?;
+    Ok(store::create_version(prev, new_content)?)
+}
+
+/// Walk an object's version history (requires READ cap).
+pub fn history(cap_id: CapId, obj_id: ObjId) -> Result<Vec<ObjId>, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ)?;
+    Ok(store::history(obj_id)?)
+}
+
+/// Copy an object under a new id (requires READ and WRITE cap).
+pub fn copy(cap_id: CapId, src: ObjId, mutate: impl FnOnce(&mut Vec<u8>)) -> Result<ObjId, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ | Rights::WRITE)?;
+    Ok(store::copy(src, mutate)?)
 }
 
 /// Read an object (requires READ cap).
@@ -42,14 +69,115 @@ pub fn read(cap_id: CapId, obj_id: ObjId) -> Result<Object, GatedError> {
     Ok(store::read(obj_id)?)
 }
 
+/// Check whether an object is stored (requires READ cap).
+pub fn contains(cap_id: CapId, obj_id: ObjId) -> Result<bool, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ)?;
+    Ok(store::contains(obj_id))
+}
+
+/// Read part of an object's content (requires READ cap).
+/// See `store::read_chunk`.
+pub fn read_chunk(cap_id: CapId, obj_id: ObjId, offset: usize, len: usize) -> Result<Vec<u8>, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ)?;
+    Ok(store::read_chunk(obj_id, offset, len)?)
+}
+
+/// Run an object through the registered executor (requires EXECUTE cap).
+/// See `store::execute`.
+pub fn execute(cap_id: CapId, obj_id: ObjId) -> Result<Vec<u8>, GatedError> {
+    caps::manager::verify(cap_id, Rights::EXECUTE)?;
+    Ok(store::execute(obj_id)?)
+}
+
+/// Append to an object (requires APPEND cap). The object keeps its id.
+pub fn append(cap_id: CapId, obj_id: ObjId, extra: &[u8]) -> Result<(), GatedError> {
+    caps::manager::verify(cap_id, Rights::APPEND)?;
+    Ok(store::append(obj_id, extra)?)
+}
+
+/// Add/remove tags on an object (requires WRITE cap).
+pub fn update_tags(cap_id: CapId, obj_id: ObjId, add: &[&str], remove: &[&str]) -> Result<(), GatedError> {
+    caps::manager::verify(cap_id, Rights::WRITE)?;
+    Ok(store::update_tags(obj_id, add, remove)?)
+}
+
+/// Rename a tag across the store (requires WRITE cap).
+pub fn rename_tag(cap_id: CapId, old: &str, new: &str) -> Result<usize, GatedError> {
+    caps::manager::verify(cap_id, Rights::WRITE)?;
+    Ok(store::rename_tag(old, new)?)
+}
+
+/// Read an object's metadata without its content (requires READ cap).
+pub fn read_meta(cap_id: CapId, obj_id: ObjId) -> Result<ObjectMeta, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ)?;
+    Ok(store::read_meta(obj_id)?)
+}
+
+/// Run a closure against a borrowed object (requires READ cap).
+pub fn with_object<R>(cap_id: CapId, obj_id: ObjId, f: impl FnOnce(&Object) -> R) -> Result<R, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ)?;
+    Ok(store::with_object(obj_id, f)?)
+}
+
 /// Query by tag (requires READ cap).
 pub fn query_by_tag(cap_id: CapId, tag: &str) -> Result<Vec<ObjId>, GatedError> {
     caps::manager::verify(cap_id, Rights::READ)?;
     Ok(store::query_by_tag(tag))
 }
 
+/// Count objects with a tag (requires READ cap).
+pub fn count_by_tag(cap_id: CapId, tag: &str) -> Result<usize, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ)?;
+    Ok(store::count_by_tag(tag))
+}
+
+/// Query tags by prefix (requires READ cap).
+pub fn query_tag_prefix(cap_id: CapId, prefix: &str) -> Result<Vec<(String, Vec<ObjId>)>, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ)?;
+    Ok(store::query_tag_prefix(prefix))
+}
+
+/// Query by metadata key/value (requires READ cap).
+pub fn query_by_meta(cap_id: CapId, key: &str, val: &str) -> Result<Vec<ObjId>, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ)?;
+    Ok(store::query_by_meta(key, val))
+}
+
+/// Scan object content for a byte string (requires READ cap).
+pub fn query_by_content(cap_id: CapId, needle: &[u8]) -> Result<Vec<ObjId>, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ)?;
+    Ok(store::query_by_content(needle))
+}
+
+/// Query objects stored after a tick (requires READ cap).
+pub fn query_created_after(cap_id: CapId, tick: u64) -> Result<Vec<ObjId>, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ)?;
+    Ok(store::query_created_after(tick))
+}
+
+/// List tags with object counts (requires READ cap).
+pub fn list_tags(cap_id: CapId) -> Result<Vec<(String, usize)>, GatedError> {
+    caps::manager::verify(cap_id, Rights::READ)?;
+    Ok(store::list_tags())
+}
+
 /// Delete an object (requires DELETE cap).
 pub fn delete(cap_id: CapId, obj_id: ObjId) -> Result<(), GatedError> {
     caps::manager::verify(cap_id, Rights::DELETE)?;
-    Ok(store::delete(obj_id)?)
+    store::delete(obj_id)?;
+    Ok(())
+}
+
+/// Delete every object carrying a tag (requires DELETE cap).
+pub fn delete_by_tag(cap_id: CapId, tag: &str) -> Result<usize, GatedError> {
+    caps::manager::verify(cap_id, Rights::DELETE)?;
+    Ok(store::delete_by_tag(tag)?)
+}
+
+/// Delete an object (requires DELETE cap) and revoke every outstanding
+/// capability to it, including `cap_id` itself if it names the object.
+/// Returns how many capabilities were revoked.
+pub fn delete_and_revoke(cap_id: CapId, obj_id: ObjId) -> Result<usize, GatedError> {
+    delete(cap_id, obj_id)?;
+    Ok(caps::manager::revoke_for_resource(&Resource::Object(obj_id.raw())))
 }
@@ -5,10 +5,13 @@
 
 pub mod store;
 pub mod gated;
+pub(crate) mod siphash;
+pub(crate) mod chunker;
 
 use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
+use crate::arch::rtc::DateTime;
 
 // ─── Core types ──────────────────────────────────────────────────
 
@@ -21,6 +24,11 @@ impl ObjId {
         self.0
     }
 
+    /// Rebuild an id from `raw()`, e.g. one passed across the syscall ABI.
+    pub fn from_raw(raw: u64) -> Self {
+        ObjId(raw)
+    }
+
     /// Compute the ObjId for given content (same hash as Object::new).
     pub fn from_content(data: &[u8]) -> Self {
         ObjId(hash_content(data))
@@ -33,14 +41,26 @@ impl core::fmt::Display for ObjId {
     }
 }
 
-/// FNV-1a hash for content addressing.
+/// Keyed SipHash-1-3 for content addressing.
 fn hash_content(data: &[u8]) -> u64 {
-    let mut hash: u64 = 0xcbf29ce484222325; // FNV offset basis
-    for &byte in data {
-        hash ^= byte as u64;
-        hash = hash.wrapping_mul(0x100000001b3); // FNV prime
+    siphash::hash(data)
+}
+
+/// Most metadata keys an object may carry.
+pub const MAX_META_KEYS: usize = 32;
+
+/// Longest metadata value accepted, in bytes.
+pub const MAX_META_VALUE_LEN: usize = 256;
+
+/// Check a metadata map against `MAX_META_KEYS` and `MAX_META_VALUE_LEN`.
+pub(crate) fn check_meta(metadata: &BTreeMap<String, String>) -> Result<(), ObjError> {
+    if metadata.len() > MAX_META_KEYS {
+        return Err(ObjError::TooManyMetaKeys);
+    }
+    if metadata.values().any(|v| v.len() > MAX_META_VALUE_LEN) {
+        return Err(ObjError::MetaValueTooLong);
     }
-    hash
+    Ok(())
 }
 
 /// An object in the store.
@@ -50,6 +70,19 @@ pub struct Object {
     pub content: Vec<u8>,
     pub tags: Vec<String>,
     pub metadata: BTreeMap<String, String>,
+    /// Previous version of this object, if any.
+    pub parent: Option<ObjId>,
+    /// Store-wide insertion order, stamped by the store on insert.
+    pub inserted_seq: u64,
+    /// Timer tick at which the store accepted the object.
+    pub created_tick: u64,
+    /// Wall-clock time at which the store accepted the object.
+    pub created_at: Option<DateTime>,
+    /// Last tick at which the object is still live (None = never expires).
+    pub expires_tick: Option<u64>,
+    /// Content is a chunk manifest rather than data; see
+    /// `store::create_chunked`.
+    pub chunked: bool,
 }
 
 impl Object {
@@ -61,6 +94,12 @@ impl Object {
             content: content.to_vec(),
             tags: Vec::new(),
             metadata: BTreeMap::new(),
+            parent: None,
+            inserted_seq: 0,
+            created_tick: 0,
+            created_at: None,
+            expires_tick: None,
+            chunked: false,
         }
     }
 
@@ -70,11 +109,57 @@ impl Object {
         self
     }
 
+    /// Builder: expire `ticks_from_now` timer ticks after now. Expiry is
+    /// enforced lazily by the store; see `store::sweep_expired`.
+    pub fn with_ttl(mut self, ticks_from_now: u64) -> Self {
+        self.expires_tick = Some(crate::arch::interrupts::ticks().saturating_add(ticks_from_now));
+        self
+    }
+
+    /// Whether the object's TTL has run out at tick `now`. The expiry tick
+    /// itself is still live.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_tick.is_some_and(|deadline| now > deadline)
+    }
+
     /// Builder: add metadata.
+    ///
+    /// Stays within the metadata limits by truncating: the value is cut to
+    /// `MAX_META_VALUE_LEN` bytes (on a char boundary), and a new key past
+    /// `MAX_META_KEYS` is dropped. Use `try_with_meta` to get an error
+    /// instead.
     pub fn with_meta(mut self, key: &str, val: &str) -> Self {
-        self.metadata.insert(String::from(key), String::from(val));
+        let mut end = val.len().min(MAX_META_VALUE_LEN);
+        while !val.is_char_boundary(end) {
+            end -= 1;
+        }
+        if self.metadata.len() < MAX_META_KEYS || self.metadata.contains_key(key) {
+            self.metadata.insert(String::from(key), String::from(&val[..end]));
+        }
         self
     }
+
+    /// Builder: add metadata, failing if it would exceed the limits.
+    /// Overwriting an existing key doesn't count against `MAX_META_KEYS`.
+    pub fn try_with_meta(mut self, key: &str, val: &str) -> Result<Self, ObjError> {
+        if val.len() > MAX_META_VALUE_LEN {
+            return Err(ObjError::MetaValueTooLong);
+        }
+        if self.metadata.len() >= MAX_META_KEYS && !self.metadata.contains_key(key) {
+            return Err(ObjError::TooManyMetaKeys);
+        }
+        self.metadata.insert(String::from(key), String::from(val));
+        Ok(self)
+    }
+}
+
+/// An object's identity and descriptive fields, without its content.
+#[derive(Debug, Clone)]
+pub struct ObjectMeta {
+    pub id: ObjId,
+    pub tags: Vec<String>,
+    pub metadata: BTreeMap<String, String>,
+    pub parent: Option<ObjId>,
 }
 
 /// Object Store errors.
@@ -82,6 +167,28 @@ impl Object {
 pub enum ObjError {
     NotFound,
     AlreadyExists,
+    /// A different object already occupies this content-addressed id.
+    HashCollision,
+    /// Content exceeds `store::MAX_OBJECT_SIZE`.
+    TooLarge,
+    /// `store::execute` was called before `store::set_executor`.
+    NoExecutor,
+    /// More than `MAX_META_KEYS` metadata keys.
+    TooManyMetaKeys,
+    /// A metadata value longer than `MAX_META_VALUE_LEN`.
+    MetaValueTooLong,
+    /// An import buffer was truncated or malformed.
+    Corrupt,
+    /// The store is read-only; see `store::freeze`.
+    Frozen,
+    /// Storing the content would exceed `store::set_memory_budget`.
+    BudgetExceeded,
+    /// A tag's schema requires a metadata key the object lacks; see
+    /// `store::register_schema`.
+    SchemaViolation,
+    /// The object is a chunk still listed by a manifest; see
+    /// `store::create_chunked`.
+    InUse,
 }
 
 impl core::fmt::Display for ObjError {
@@ -89,6 +196,16 @@ impl core::fmt::Display for ObjError {
         match self {
             ObjError::NotFound => write!(f, "not found"),
             ObjError::AlreadyExists => write!(f, "already exists"),
+            ObjError::HashCollision => write!(f, "hash collision"),
+            ObjError::TooLarge => write!(f, "too large"),
+            ObjError::NoExecutor => write!(f, "no executor registered"),
+            ObjError::TooManyMetaKeys => write!(f, "too many metadata keys"),
+            ObjError::MetaValueTooLong => write!(f, "metadata value too long"),
+            ObjError::Corrupt => write!(f, "corrupt data"),
+            ObjError::Frozen => write!(f, "store is frozen"),
+            ObjError::BudgetExceeded => write!(f, "memory budget exceeded"),
+            ObjError::SchemaViolation => write!(f, "schema violation"),
+            ObjError::InUse => write!(f, "in use by a chunked object"),
         }
     }
 }
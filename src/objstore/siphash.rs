@@ -0,0 +1,76 @@
+//! SipHash-1-3 over byte slices, used for content addressing.
+//!
+//! `core::hash::SipHasher13` is unstable and `core`'s `SipHasher` is
+//! deprecated, so the kernel carries its own small implementation.
+
+/// Compile-time key. Changing it changes every `ObjId`.
+const KEY: (u64, u64) = (0x7061_7279_616e_7461, 0x6f62_6a73_746f_7265);
+
+struct State {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+}
+
+impl State {
+    fn new(k0: u64, k1: u64) -> Self {
+        State {
+            v0: k0 ^ 0x736f_6d65_7073_6575,
+            v1: k1 ^ 0x646f_7261_6e64_6f6d,
+            v2: k0 ^ 0x6c79_6765_6e65_7261,
+            v3: k1 ^ 0x7465_6462_7974_6573,
+        }
+    }
+
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, m: u64, rounds: usize) {
+        self.v3 ^= m;
+        for _ in 0..rounds {
+            self.round();
+        }
+        self.v0 ^= m;
+    }
+}
+
+/// Keyed SipHash with `C` compression and `D` finalization rounds.
+pub fn siphash<const C: usize, const D: usize>(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut state = State::new(k0, k1);
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(chunk);
+        state.compress(u64::from_le_bytes(word), C);
+    }
+
+    // Final block: remaining bytes plus the length in the top byte
+    let mut last = [0u8; 8];
+    let tail = chunks.remainder();
+    last[..tail.len()].copy_from_slice(tail);
+    last[7] = data.len() as u8;
+    state.compress(u64::from_le_bytes(last), C);
+
+    state.v2 ^= 0xff;
+    for _ in 0..D {
+        state.round();
+    }
+    state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+}
+
+/// SipHash-1-3 of `data` under the kernel's content-addressing key.
+pub fn hash(data: &[u8]) -> u64 {
+    siphash::<1, 3>(KEY.0, KEY.1, data)
+}
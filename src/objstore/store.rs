@@ -1,22 +1,73 @@
 //! In-memory Object Store backed by BTreeMap.
 
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
 use spin::Mutex;
-use super::{ObjId, Object, ObjError};
+use crate::arch::{interrupts::ticks, rtc::{self, DateTime}};
+use crate::wire::{Reader, Writer};
+use super::{chunker, check_meta, ObjId, Object, ObjectMeta, ObjError};
+
+/// Largest object content the store accepts, in bytes (16 KiB).
+pub const MAX_OBJECT_SIZE: usize = 16 * 1024;
 
 /// Global object store instance.
 static STORE: Mutex<StoreInner> = Mutex::new(StoreInner::new());
 
+/// Count of changes committed so far; see `version`.
+static VERSION: AtomicU64 = AtomicU64::new(0);
+
+/// Callback that runs object content as code and returns its output.
+pub type Executor = fn(&[u8]) -> Vec<u8>;
+
+/// The registered executor; see `set_executor`.
+static EXECUTOR: Mutex<Option<Executor>> = Mutex::new(None);
+
+#[derive(Clone)]
 struct StoreInner {
     objects: Option<BTreeMap<ObjId, Object>>,
     tag_index: Option<BTreeMap<String, Vec<ObjId>>>,
+    meta_index: Option<BTreeMap<(String, String), Vec<ObjId>>>,
+    /// Access order, least recently used at the front.
+    recency: VecDeque<ObjId>,
+    /// Maximum number of objects before `create` evicts (None = unbounded).
+    capacity: Option<usize>,
+    /// Sequence number for the next inserted object.
+    next_seq: u64,
+    /// Changes not yet delivered to subscribers; see `notify`.
+    events: Vec<StoreEvent>,
+    /// Set by `freeze`: mutations fail with `Frozen`.
+    frozen: bool,
+    /// Most content bytes the store may hold (None = unbounded).
+    memory_budget: Option<usize>,
+    /// Content bytes of every stored object, kept for `memory_budget`.
+    content_bytes: usize,
+    /// Metadata keys required of objects carrying each tag; see
+    /// `register_schema`.
+    schemas: Option<BTreeMap<String, Vec<String>>>,
+    /// Number of stored manifests listing each chunk; a chunk with a
+    /// count here is pinned and can't be deleted, evicted or expired.
+    chunk_refs: Option<BTreeMap<ObjId, usize>>,
 }
 
 impl StoreInner {
     const fn new() -> Self {
-        Self { objects: None, tag_index: None }
+        Self {
+            objects: None,
+            tag_index: None,
+            meta_index: None,
+            recency: VecDeque::new(),
+            capacity: None,
+            next_seq: 0,
+            events: Vec::new(),
+            frozen: false,
+            memory_budget: None,
+            content_bytes: 0,
+            schemas: None,
+            chunk_refs: None,
+        }
     }
 
     fn objects(&mut self) -> &mut BTreeMap<ObjId, Object> {
@@ -26,64 +77,1186 @@ impl StoreInner {
     fn tag_index(&mut self) -> &mut BTreeMap<String, Vec<ObjId>> {
         self.tag_index.get_or_insert_with(BTreeMap::new)
     }
+
+    fn meta_index(&mut self) -> &mut BTreeMap<(String, String), Vec<ObjId>> {
+        self.meta_index.get_or_insert_with(BTreeMap::new)
+    }
+
+    /// Whether a stored manifest still lists `id` as one of its chunks.
+    fn is_pinned(&self, id: ObjId) -> bool {
+        self.chunk_refs.as_ref().is_some_and(|refs| refs.contains_key(&id))
+    }
+
+    /// Add one reference to each chunk in `ids`.
+    fn pin(&mut self, ids: &[ObjId]) {
+        let refs = self.chunk_refs.get_or_insert_with(BTreeMap::new);
+        for &id in ids {
+            *refs.entry(id).or_default() += 1;
+        }
+    }
+
+    /// Drop one reference to each chunk in `ids`, unpinning any left with
+    /// none.
+    fn unpin(&mut self, ids: &[ObjId]) {
+        let refs = self.chunk_refs.get_or_insert_with(BTreeMap::new);
+        for id in ids {
+            if let Some(count) = refs.get_mut(id) {
+                *count -= 1;
+                if *count == 0 {
+                    refs.remove(id);
+                }
+            }
+        }
+    }
+
+    /// Fail with `BudgetExceeded` if `extra` more content bytes would
+    /// push the store past its memory budget.
+    fn reserve(&self, extra: usize) -> Result<(), ObjError> {
+        let total = self.content_bytes.saturating_add(extra);
+        if self.memory_budget.is_some_and(|budget| total > budget) {
+            return Err(ObjError::BudgetExceeded);
+        }
+        Ok(())
+    }
+
+    /// Fail with `SchemaViolation` unless `metadata` has every key that
+    /// the schema of each of `tags` requires.
+    fn check_schemas<T: AsRef<str>>(&self, tags: &[T], metadata: &BTreeMap<String, String>) -> Result<(), ObjError> {
+        let Some(schemas) = self.schemas.as_ref() else {
+            return Ok(());
+        };
+        let satisfied = tags.iter()
+            .filter_map(|tag| schemas.get(tag.as_ref()))
+            .flatten()
+            .all(|key| metadata.contains_key(key));
+        if !satisfied {
+            return Err(ObjError::SchemaViolation);
+        }
+        Ok(())
+    }
+
+    /// Fail with `Frozen` while `freeze` is in effect.
+    fn writable(&self) -> Result<(), ObjError> {
+        if self.frozen {
+            return Err(ObjError::Frozen);
+        }
+        Ok(())
+    }
+
+    /// Insert an object and index its tags and metadata.
+    ///
+    /// If the store is at capacity, the least recently used object is
+    /// evicted first and returned alongside the new id.
+    ///
+    /// Every check runs before anything is touched, so a rejected insert
+    /// (too large, over the metadata limits or memory budget, missing keys
+    /// a tag's schema requires, duplicate or colliding id) leaves the
+    /// objects, indices and recency exactly as they were. All of this
+    /// happens under the caller's single `STORE` guard.
+    ///
+    /// `now` becomes the object's `created_at`. Callers read the RTC
+    /// before taking the lock, since a read can spin across an update.
+    fn insert(&mut self, mut obj: Object, now: DateTime) -> Result<(ObjId, Option<Object>), ObjError> {
+        let id = obj.id;
+
+        // Validate
+        if obj.content.len() > MAX_OBJECT_SIZE {
+            return Err(ObjError::TooLarge);
+        }
+        check_meta(&obj.metadata)?;
+        self.check_schemas(&obj.tags, &obj.metadata)?;
+        // Bytes an eviction below would free aren't counted
+        self.reserve(obj.content.len())?;
+        // Same id: either a genuine duplicate or a hash collision
+        if let Some(existing) = self.objects().get(&id) {
+            return Err(if existing.content == obj.content {
+                ObjError::AlreadyExists
+            } else {
+                ObjError::HashCollision
+            });
+        }
+
+        // Commit. Pin a manifest's chunks first so eviction can't take them
+        if obj.chunked {
+            self.pin(&manifest_ids(&obj.content));
+        }
+        let full = self.capacity.is_some_and(|cap| self.objects().len() >= cap);
+        let evicted = if full { self.evict_lru() } else { None };
+
+        // A tag listed twice would otherwise be indexed twice
+        let mut seen: Vec<String> = Vec::new();
+        obj.tags.retain(|t| {
+            let first = !seen.contains(t);
+            if first {
+                seen.push(t.clone());
+            }
+            first
+        });
+
+        let tag_index = self.tag_index.get_or_insert_with(BTreeMap::new);
+        for tag in &obj.tags {
+            tag_index.entry(tag.clone()).or_default().push(id);
+        }
+        let meta_index = self.meta_index.get_or_insert_with(BTreeMap::new);
+        for (key, val) in &obj.metadata {
+            meta_index.entry((key.clone(), val.clone())).or_default().push(id);
+        }
+
+        obj.inserted_seq = self.next_seq;
+        self.next_seq += 1;
+        // Stamped here rather than in `Object::new`, which may run long
+        // before the object is actually stored
+        obj.created_tick = ticks();
+        obj.created_at = Some(now);
+        self.content_bytes += obj.content.len();
+        self.objects().insert(id, obj);
+        self.recency.push_back(id);
+        self.events.push(StoreEvent::Created(id));
+        Ok((id, evicted))
+    }
+
+    /// Remove an object and drop it from every index.
+    fn remove(&mut self, id: ObjId) -> Option<Object> {
+        let obj = self.objects().remove(&id)?;
+        if obj.chunked {
+            self.unpin(&manifest_ids(&obj.content));
+        }
+        self.content_bytes -= obj.content.len();
+        self.recency.retain(|i| *i != id);
+        self.events.push(StoreEvent::Deleted(id));
+
+        // Clean up tag index
+        for tag in &obj.tags {
+            self.unindex_tag(tag, id);
+        }
+
+        // Clean up metadata index
+        for (key, val) in &obj.metadata {
+            self.unindex_meta(key, val, id);
+        }
+        Some(obj)
+    }
+
+    /// Put back an object taken out by `remove`, keeping its sequence
+    /// number and timestamps. Skips every check, so it cannot fail; only
+    /// for rolling back to a state that was already stored.
+    fn reinsert(&mut self, obj: Object) {
+        let id = obj.id;
+        if obj.chunked {
+            self.pin(&manifest_ids(&obj.content));
+        }
+        for tag in &obj.tags {
+            self.tag_index().entry(tag.clone()).or_default().push(id);
+        }
+        for (key, val) in &obj.metadata {
+            self.meta_index().entry((key.clone(), val.clone())).or_default().push(id);
+        }
+        self.content_bytes += obj.content.len();
+        self.recency.push_back(id);
+        self.objects().insert(id, obj);
+    }
+
+    /// Whether `id`'s TTL has run out, removing it if so. A frozen store
+    /// keeps the expired object but still reports it. Pinned chunks never
+    /// expire.
+    fn expire(&mut self, id: ObjId) -> bool {
+        let expired = !self.is_pinned(id) && self.objects.as_ref()
+            .and_then(|m| m.get(&id))
+            .is_some_and(|o| o.is_expired(ticks()));
+        if expired && !self.frozen {
+            self.remove(id);
+        }
+        expired
+    }
+
+    /// Fold `incoming`'s tags and metadata into the stored object with the
+    /// same id. Tags are unioned; incoming metadata values overwrite
+    /// existing ones for the same key.
+    fn merge(&mut self, incoming: Object) -> Result<(), ObjError> {
+        let id = incoming.id;
+        let existing = self.objects.as_ref()
+            .and_then(|m| m.get(&id))
+            .ok_or(ObjError::NotFound)?;
+        let mut merged = existing.metadata.clone();
+        merged.extend(incoming.metadata.clone());
+        check_meta(&merged)?;
+        let tags: Vec<&String> = existing.tags.iter().chain(&incoming.tags).collect();
+        self.check_schemas(&tags, &merged)?;
+
+        // Schemas were checked above against the merged metadata
+        self.retag_unchecked(id, &incoming.tags, &[] as &[&str])?;
+
+        for (key, val) in incoming.metadata {
+            let obj = self.objects().get_mut(&id).ok_or(ObjError::NotFound)?;
+            let old = obj.metadata.insert(key.clone(), val.clone());
+            match old {
+                Some(old) if old == val => continue,
+                Some(old) => self.unindex_meta(&key, &old, id),
+                None => {}
+            }
+            self.meta_index().entry((key, val)).or_default().push(id);
+        }
+        self.touch(id);
+        self.events.push(StoreEvent::Updated(id));
+        Ok(())
+    }
+
+    /// Evict the least recently used object that isn't a pinned chunk,
+    /// returning it.
+    fn evict_lru(&mut self) -> Option<Object> {
+        let id = *self.recency.iter().find(|id| !self.is_pinned(**id))?;
+        self.remove(id)
+    }
+
+    /// Mark an object as most recently used.
+    fn touch(&mut self, id: ObjId) {
+        if let Some(pos) = self.recency.iter().position(|i| *i == id) {
+            self.recency.remove(pos);
+            self.recency.push_back(id);
+        }
+    }
+
+    /// Add and remove tags on an object, keeping the tag index in sync.
+    /// Returns the tags actually `(added, removed)`.
+    ///
+    /// Fails with `SchemaViolation`, changing nothing, if the resulting
+    /// tag set has a schema the object's metadata doesn't satisfy.
+    fn retag<A: AsRef<str>, R: AsRef<str>>(
+        &mut self,
+        id: ObjId,
+        add: &[A],
+        remove: &[R],
+    ) -> Result<(Vec<String>, Vec<String>), ObjError> {
+        let obj = self.objects.as_ref()
+            .and_then(|m| m.get(&id))
+            .ok_or(ObjError::NotFound)?;
+        let tags: Vec<&str> = obj.tags.iter()
+            .map(String::as_str)
+            .filter(|t| !remove.iter().any(|r| r.as_ref() == *t))
+            .chain(add.iter().map(AsRef::as_ref))
+            .collect();
+        self.check_schemas(&tags, &obj.metadata)?;
+        self.retag_unchecked(id, add, remove)
+    }
+
+    /// `retag` without the schema check, for rollbacks restoring a tag
+    /// set that was already stored.
+    fn retag_unchecked<A: AsRef<str>, R: AsRef<str>>(
+        &mut self,
+        id: ObjId,
+        add: &[A],
+        remove: &[R],
+    ) -> Result<(Vec<String>, Vec<String>), ObjError> {
+        let obj = self.objects()
+            .get_mut(&id)
+            .ok_or(ObjError::NotFound)?;
+
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for tag in remove.iter().map(AsRef::as_ref) {
+            if let Some(pos) = obj.tags.iter().position(|t| t == tag) {
+                removed.push(obj.tags.remove(pos));
+            }
+        }
+        for tag in add.iter().map(AsRef::as_ref) {
+            if !obj.tags.iter().any(|t| t == tag) {
+                obj.tags.push(String::from(tag));
+                added.push(String::from(tag));
+            }
+        }
+
+        for tag in &removed {
+            self.unindex_tag(tag, id);
+        }
+        for tag in &added {
+            self.tag_index().entry(tag.clone()).or_default().push(id);
+        }
+        Ok((added, removed))
+    }
+
+    /// Remove `id` from a metadata index entry, pruning the entry once empty.
+    fn unindex_meta(&mut self, key: &str, val: &str, id: ObjId) {
+        let entry = (String::from(key), String::from(val));
+        if let Some(ids) = self.meta_index().get_mut(&entry) {
+            ids.retain(|i| *i != id);
+            if ids.is_empty() {
+                self.meta_index().remove(&entry);
+            }
+        }
+    }
+
+    /// Remove `id` from a tag's index entry, pruning the entry once empty.
+    fn unindex_tag(&mut self, tag: &str, id: ObjId) {
+        if let Some(ids) = self.tag_index().get_mut(tag) {
+            ids.retain(|i| *i != id);
+            if ids.is_empty() {
+                self.tag_index().remove(tag);
+            }
+        }
+    }
+
+    /// Concatenate the chunks listed in a manifest's content. A missing
+    /// chunk fails with `NotFound`; a manifest that isn't a whole number
+    /// of ids fails with `Corrupt`.
+    fn reassemble(&self, manifest: &[u8]) -> Result<Vec<u8>, ObjError> {
+        let objects = self.objects.as_ref().ok_or(ObjError::NotFound)?;
+        let mut r = Reader::new(manifest);
+        let mut content = Vec::new();
+        while !r.is_empty() {
+            let id = ObjId(r.u64().ok_or(ObjError::Corrupt)?);
+            let chunk = objects.get(&id).ok_or(ObjError::NotFound)?;
+            content.extend_from_slice(&chunk.content);
+        }
+        Ok(content)
+    }
+}
+
+/// A change to the store, delivered to `subscribe`d callbacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreEvent {
+    Created(ObjId),
+    /// Deleted explicitly or evicted.
+    Deleted(ObjId),
+    /// Tags, metadata or content changed in place.
+    Updated(ObjId),
+}
+
+/// A change-notification callback.
+pub type Subscriber = fn(StoreEvent);
+
+/// Most callbacks `subscribe` accepts.
+pub const MAX_SUBSCRIBERS: usize = 8;
+
+/// Registered callbacks, in a fixed array so notifying never allocates.
+static SUBSCRIBERS: Mutex<[Option<Subscriber>; MAX_SUBSCRIBERS]> = Mutex::new([None; MAX_SUBSCRIBERS]);
+
+/// Register `cb` to be called after every committed change. Returns
+/// false if all `MAX_SUBSCRIBERS` slots are taken.
+///
+/// Callbacks run after the store lock is released, so they may use the
+/// store themselves. `restore` replaces the store wholesale and reports
+/// nothing.
+pub fn subscribe(cb: Subscriber) -> bool {
+    let mut subs = SUBSCRIBERS.lock();
+    match subs.iter_mut().find(|s| s.is_none()) {
+        Some(slot) => {
+            *slot = Some(cb);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Run a mutation under the store lock, then deliver the events it queued.
+fn mutate<R>(f: impl FnOnce(&mut StoreInner) -> R) -> R {
+    mutate_versioned(f).0
 }
 
-/// Store an object. Returns its content-addressed ID.
-pub fn create(obj: Object) -> Result<ObjId, ObjError> {
+/// Like `mutate`, also returning the store version right after `f`.
+///
+/// Every queued event is one committed change, so the version advances
+/// by the number of events, under the same lock as the change itself.
+fn mutate_versioned<R>(f: impl FnOnce(&mut StoreInner) -> R) -> (R, u64) {
     let mut store = STORE.lock();
-    let id = obj.id;
+    let result = f(&mut store);
+    let changes = store.events.len() as u64;
+    let version = VERSION.fetch_add(changes, Ordering::SeqCst) + changes;
+    drop(store);
+    notify();
+    (result, version)
+}
+
+/// Current store version: the number of creates, deletes (including
+/// evictions) and updates committed since boot. Never decreases, and a
+/// rolled-back `transaction` leaves it unchanged. `restore` counts as one
+/// change.
+pub fn version() -> u64 {
+    VERSION.load(Ordering::SeqCst)
+}
 
-    if store.objects().contains_key(&id) {
-        return Err(ObjError::AlreadyExists);
+/// Deliver queued events, oldest first, with no store lock held.
+fn notify() {
+    let events = core::mem::take(&mut STORE.lock().events);
+    if events.is_empty() {
+        return;
     }
+    let subs = *SUBSCRIBERS.lock();
+    for event in events {
+        for cb in subs.iter().flatten() {
+            cb(event);
+        }
+    }
+}
 
-    // Update tag index
-    for tag in &obj.tags {
-        store.tag_index()
-            .entry(tag.clone())
-            .or_insert_with(Vec::new)
-            .push(id);
+/// A deep copy of the whole store, taken by `snapshot`.
+///
+/// Holds its own clone of every object and index, so a live snapshot
+/// roughly doubles the store's heap footprint.
+pub struct StoreSnapshot {
+    inner: StoreInner,
+}
+
+impl StoreSnapshot {
+    /// Number of objects captured.
+    pub fn count(&self) -> usize {
+        self.inner.objects.as_ref().map_or(0, |m| m.len())
     }
+}
+
+/// Capture the entire store (objects, indices, recency, capacity).
+pub fn snapshot() -> StoreSnapshot {
+    StoreSnapshot { inner: STORE.lock().clone() }
+}
+
+/// Replace the live store with a snapshot, discarding all changes since.
+///
+/// Works even while frozen, and leaves the frozen state, memory budget
+/// and schemas as they are.
+pub fn restore(snapshot: StoreSnapshot) {
+    let mut store = STORE.lock();
+    let (frozen, memory_budget) = (store.frozen, store.memory_budget);
+    let schemas = store.schemas.take();
+    *store = snapshot.inner;
+    store.frozen = frozen;
+    store.memory_budget = memory_budget;
+    store.schemas = schemas;
+    VERSION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Make the store read-only: until `unfreeze`, every operation that would
+/// change objects or indices fails with `Frozen` (or, for `sweep_expired`,
+/// removes nothing), while reads and queries keep working. Expired
+/// objects read as `NotFound` but stay stored.
+///
+/// Meant for taking a consistent `export` without holding the store lock
+/// for the whole serialization.
+pub fn freeze() {
+    STORE.lock().frozen = true;
+}
+
+/// Undo `freeze`.
+pub fn unfreeze() {
+    STORE.lock().frozen = false;
+}
+
+/// Store an object. Returns its content-addressed ID and the store
+/// `version` right after the insert.
+///
+/// Fails with `AlreadyExists` if identical content is already stored, or
+/// `HashCollision` if different content occupies the same id. Content
+/// larger than `MAX_OBJECT_SIZE` is rejected with `TooLarge`, and
+/// metadata over the limits with `TooManyMetaKeys`/`MetaValueTooLong`.
+pub fn create(obj: Object) -> Result<(ObjId, u64), ObjError> {
+    let now = rtc::now();
+    let (result, version) = mutate_versioned(|store| {
+        store.writable()?;
+        store.insert(obj, now)
+    });
+    result.map(|(id, _)| (id, version))
+}
+
+/// Store `content` as content-defined chunks plus a manifest, returning
+/// the manifest's id.
+///
+/// Chunk boundaries come from a rolling hash (see `chunker`), so content
+/// sharing a run of bytes with earlier chunked content mostly splits into
+/// the same chunks. Each chunk is stored as an ordinary untagged object
+/// under its own content hash; a chunk already present is reused rather
+/// than stored twice. The manifest lists the chunk ids, is marked
+/// `chunked`, and `read` reassembles the full content from it, so
+/// `content` itself may exceed `MAX_OBJECT_SIZE`.
+///
+/// Chunks are pinned while any stored manifest lists them: `delete` and
+/// `transaction` refuse them with `InUse`, and eviction, expiry and
+/// `delete_by_tag` pass them over. Deleting the last manifest using a
+/// chunk unpins it but leaves it stored. If storing a chunk fails the
+/// chunks stored so far are kept, unpinned.
+pub fn create_chunked(content: &[u8]) -> Result<ObjId, ObjError> {
+    let now = rtc::now();
+    mutate(|store| {
+        store.writable()?;
+        let mut ids = Vec::new();
+        // Each chunk is pinned as soon as it is stored, so storing the
+        // next one can't evict it
+        let stored = chunker::split(content).into_iter().try_for_each(|chunk| {
+            let id = match store.insert(Object::new(chunk), now) {
+                Ok((id, _)) => id,
+                Err(ObjError::AlreadyExists) => ObjId::from_content(chunk),
+                Err(e) => return Err(e),
+            };
+            store.pin(&[id]);
+            ids.push(id);
+            Ok(())
+        });
+        // The manifest's insert takes over these pins
+        store.unpin(&ids);
+        stored?;
+
+        let mut manifest = Writer::new();
+        for id in &ids {
+            manifest.u64(id.0);
+        }
+        let mut obj = Object::new(&manifest.finish());
+        obj.chunked = true;
+        store.insert(obj, now).map(|(id, _)| id)
+    })
+}
 
-    store.objects().insert(id, obj);
-    Ok(id)
+/// Chunk ids listed in a manifest's content.
+fn manifest_ids(manifest: &[u8]) -> Vec<ObjId> {
+    manifest.chunks_exact(8)
+        .map(|b| ObjId(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])))
+        .collect()
 }
 
-/// Read an object by ID.
+/// Store an object, or merge it into an identical one already stored.
+///
+/// Returns the id and whether the object was newly inserted. If the same
+/// content is already present its tags are unioned with `obj`'s and
+/// `obj`'s metadata overwrites matching keys, instead of failing with
+/// `AlreadyExists`. A `HashCollision` or `TooLarge` still fails.
+pub fn get_or_create(obj: Object) -> Result<(ObjId, bool), ObjError> {
+    let now = rtc::now();
+    mutate(|store| {
+        store.writable()?;
+        let id = obj.id;
+        let same = store.objects.as_ref()
+            .and_then(|m| m.get(&id))
+            .map(|existing| existing.content == obj.content);
+        match same {
+            Some(true) => store.merge(obj).map(|_| (id, false)),
+            _ => store.insert(obj, now).map(|(id, _)| (id, true)),
+        }
+    })
+}
+
+/// Like `create`, but also reports the id of any object evicted to make
+/// room under the capacity limit.
+pub fn create_evicting(obj: Object) -> Result<(ObjId, Option<ObjId>), ObjError> {
+    let now = rtc::now();
+    mutate(|store| {
+        store.writable()?;
+        store.insert(obj, now).map(|(id, evicted)| (id, evicted.map(|o| o.id)))
+    })
+}
+
+/// Limit the store to `max_objects` (at least one). Once full, `create`
+/// evicts the least recently read object; if the store is already over
+/// the new limit, the excess is evicted immediately — unless the store
+/// is frozen, in which case the limit only applies to later creates.
+pub fn set_capacity(max_objects: usize) {
+    mutate(|store| {
+        let cap = max_objects.max(1);
+        store.capacity = Some(cap);
+        while !store.frozen && store.objects().len() > cap {
+            if store.evict_lru().is_none() {
+                break;
+            }
+        }
+    })
+}
+
+/// Limit the total content bytes of all stored objects to `bytes`. Once
+/// the limit is reached, `create` (and every other path that adds or
+/// grows content) fails with `BudgetExceeded` instead of evicting.
+///
+/// Only content is counted, not tags, metadata or index overhead, so the
+/// heap used is somewhat higher. Lowering the budget below what is
+/// already stored removes nothing; it just blocks further growth.
+/// `usize::MAX` effectively lifts the limit.
+pub fn set_memory_budget(bytes: usize) {
+    STORE.lock().memory_budget = Some(bytes);
+}
+
+/// Require every object tagged `tag` to carry each of `required_keys` in
+/// its metadata, replacing any schema already registered for `tag`.
+///
+/// Inserting an object, retagging one (`update_tags`, `rename_tag`, or
+/// a `transaction`'s `UpdateTags`), or merging into one via
+/// `get_or_create` fails with `SchemaViolation` if the result would miss
+/// a key; an object with several tags must satisfy every one
+/// of their schemas. Objects already stored are not rechecked.
+pub fn register_schema(tag: &str, required_keys: &[&str]) {
+    let keys = required_keys.iter().map(|k| String::from(*k)).collect();
+    STORE.lock().schemas.get_or_insert_with(BTreeMap::new).insert(String::from(tag), keys);
+}
+
+/// Total content bytes currently stored, as counted against the memory
+/// budget.
+pub fn memory_used() -> usize {
+    STORE.lock().content_bytes
+}
+
+/// Store a new version of `prev` with `new_content`.
+///
+/// The new object gets a fresh content-addressed id, inherits the tags and
+/// metadata of `prev`, and links back to it via `parent`.
+pub fn create_version(prev: ObjId, new_content: &[u8]) -> Result<ObjId, ObjError> {
+    let now = rtc::now();
+    mutate(|store| {
+        store.writable()?;
+        let old = store.objects()
+            .get(&prev)
+            .ok_or(ObjError::NotFound)?;
+
+        let mut obj = Object::new(new_content);
+        obj.tags = old.tags.clone();
+        obj.metadata = old.metadata.clone();
+        obj.parent = Some(prev);
+        // Keep `prev` from being the eviction victim for its own successor
+        store.touch(prev);
+        store.insert(obj, now).map(|(id, _)| id)
+    })
+}
+
+/// Read an object's id, tags and metadata without cloning its content.
+pub fn read_meta(id: ObjId) -> Result<ObjectMeta, ObjError> {
+    with_object(id, |obj| ObjectMeta {
+        id: obj.id,
+        tags: obj.tags.clone(),
+        metadata: obj.metadata.clone(),
+        parent: obj.parent,
+    })
+}
+
+/// Run `f` against a borrowed object while the store lock is held.
+///
+/// Avoids cloning content; `f` must not call back into the store.
+/// Marks the object as most recently used. Like `read`, an expired object
+/// is deleted and reported as `NotFound`.
+pub fn with_object<R>(id: ObjId, f: impl FnOnce(&Object) -> R) -> Result<R, ObjError> {
+    mutate(|store| {
+        if store.expire(id) {
+            return Err(ObjError::NotFound);
+        }
+        let result = store.objects.as_ref()
+            .and_then(|m| m.get(&id))
+            .map(f)
+            .ok_or(ObjError::NotFound)?;
+        store.touch(id);
+        Ok(result)
+    })
+}
+
+/// Copy an object under a new id.
+///
+/// `mutate` is applied to a copy of the source content (outside the store
+/// lock), then the result is rehashed and stored with the source's tags
+/// and metadata. If the content is left unchanged the copy would share the
+/// source's id, so `AlreadyExists` is returned.
+pub fn copy(src: ObjId, mutate: impl FnOnce(&mut Vec<u8>)) -> Result<ObjId, ObjError> {
+    let source = read(src)?;
+    let mut content = source.content;
+    mutate(&mut content);
+
+    let mut obj = Object::new(&content);
+    obj.tags = source.tags;
+    obj.metadata = source.metadata;
+    create(obj).map(|(id, _)| id)
+}
+
+/// Version history of an object, newest to oldest, starting with `id`.
+///
+/// The walk stops early if an ancestor has since been deleted.
+pub fn history(id: ObjId) -> Result<Vec<ObjId>, ObjError> {
+    let store = STORE.lock();
+    let objects = store.objects.as_ref().ok_or(ObjError::NotFound)?;
+    let mut obj = objects.get(&id).ok_or(ObjError::NotFound)?;
+
+    let mut chain = vec![id];
+    while let Some(parent) = obj.parent {
+        match objects.get(&parent) {
+            Some(p) => {
+                chain.push(parent);
+                obj = p;
+            }
+            None => break,
+        }
+    }
+    Ok(chain)
+}
+
+/// Read an object by ID. Marks it as most recently used.
+///
+/// An expired object is deleted on the spot and reads as `NotFound`. A
+/// `chunked` manifest comes back with its chunks reassembled into
+/// `content` (the flag stays set); other accessors such as `with_object`
+/// and `read_chunk` see the raw manifest.
 pub fn read(id: ObjId) -> Result<Object, ObjError> {
+    mutate(|store| {
+        if store.expire(id) {
+            return Err(ObjError::NotFound);
+        }
+        let mut obj = store.objects.as_ref()
+            .and_then(|m| m.get(&id))
+            .cloned()
+            .ok_or(ObjError::NotFound)?;
+        if obj.chunked {
+            obj.content = store.reassemble(&obj.content)?;
+        }
+        store.touch(id);
+        Ok(obj)
+    })
+}
+
+/// Whether an object with `id` is stored, without cloning it or touching
+/// LRU recency. Pair with `ObjId::from_content` to check for content
+/// before storing it. An expired object counts as absent.
+pub fn contains(id: ObjId) -> bool {
     let store = STORE.lock();
     store.objects.as_ref()
         .and_then(|m| m.get(&id))
-        .cloned()
-        .ok_or(ObjError::NotFound)
+        .is_some_and(|o| !o.is_expired(ticks()))
+}
+
+/// Read up to `len` bytes of an object's content starting at `offset`,
+/// without cloning the rest. A range running past the end is clamped; an
+/// offset at or past the end yields an empty chunk.
+pub fn read_chunk(id: ObjId, offset: usize, len: usize) -> Result<Vec<u8>, ObjError> {
+    with_object(id, |obj| {
+        let start = offset.min(obj.content.len());
+        let end = start.saturating_add(len).min(obj.content.len());
+        obj.content[start..end].to_vec()
+    })
+}
+
+/// Register the callback that `execute` hands object content to,
+/// replacing any previous one.
+pub fn set_executor(executor: Executor) {
+    *EXECUTOR.lock() = Some(executor);
+}
+
+/// Run an object's content through the registered executor and return
+/// its output. Fails with `NoExecutor` if none is registered.
+///
+/// The executor is called outside the store lock, so it may itself use
+/// the store.
+pub fn execute(id: ObjId) -> Result<Vec<u8>, ObjError> {
+    let executor = (*EXECUTOR.lock()).ok_or(ObjError::NoExecutor)?;
+    let content = read(id)?.content;
+    Ok(executor(&content))
+}
+
+/// Append bytes to an existing object's content.
+///
+/// The object keeps its original id, so after an append the id no longer
+/// matches `ObjId::from_content` of the stored content — appendable objects
+/// give up the content-addressing invariant (and fail `verify_integrity`).
+pub fn append(id: ObjId, extra: &[u8]) -> Result<(), ObjError> {
+    mutate(|store| {
+        store.writable()?;
+        store.reserve(extra.len())?;
+        let obj = store.objects()
+            .get_mut(&id)
+            .ok_or(ObjError::NotFound)?;
+        if obj.content.len() + extra.len() > MAX_OBJECT_SIZE {
+            return Err(ObjError::TooLarge);
+        }
+        obj.content.extend_from_slice(extra);
+        store.content_bytes += extra.len();
+        store.events.push(StoreEvent::Updated(id));
+        Ok(())
+    })
+}
+
+/// Add and remove tags on an existing object, keeping the tag index in sync.
+///
+/// Adding a tag the object already has and removing one it lacks are no-ops.
+pub fn update_tags(id: ObjId, add: &[&str], remove: &[&str]) -> Result<(), ObjError> {
+    mutate(|store| {
+        store.writable()?;
+        let (added, removed) = store.retag(id, add, remove)?;
+        if !added.is_empty() || !removed.is_empty() {
+            store.events.push(StoreEvent::Updated(id));
+        }
+        Ok(())
+    })
+}
+
+/// Rename tag `old` to `new` on every object carrying it, returning how
+/// many objects were retagged.
+///
+/// Objects that already carry `new` just lose `old`, so tags stay unique
+/// per object and the two index entries merge. Renaming a tag to itself
+/// or renaming an unused tag changes nothing and returns 0. If any
+/// renamed object would violate `new`'s schema, nothing is renamed and
+/// `SchemaViolation` is returned.
+pub fn rename_tag(old: &str, new: &str) -> Result<usize, ObjError> {
+    mutate(|store| {
+        store.writable()?;
+        if old == new {
+            return Ok(0);
+        }
+        let Some(ids) = store.tag_index().get(old).cloned() else {
+            return Ok(0);
+        };
+        // Check every object before renaming any
+        for &id in &ids {
+            if let Some(obj) = store.objects.as_ref().and_then(|m| m.get(&id)) {
+                let tags: Vec<&str> = obj.tags.iter()
+                    .map(String::as_str)
+                    .filter(|t| *t != old)
+                    .chain([new])
+                    .collect();
+                store.check_schemas(&tags, &obj.metadata)?;
+            }
+        }
+        store.tag_index().remove(old);
+        for &id in &ids {
+            let Some(obj) = store.objects().get_mut(&id) else {
+                continue;
+            };
+            obj.tags.retain(|t| t != old);
+            if !obj.tags.iter().any(|t| t == new) {
+                obj.tags.push(String::from(new));
+                store.tag_index().entry(String::from(new)).or_default().push(id);
+            }
+            store.events.push(StoreEvent::Updated(id));
+        }
+        Ok(ids.len())
+    })
+}
+
+/// One operation in a `transaction`.
+#[derive(Debug, Clone)]
+pub enum StoreOp {
+    Create(Object),
+    Delete(ObjId),
+    UpdateTags { id: ObjId, add: Vec<String>, remove: Vec<String> },
+}
+
+/// How to reverse an applied `StoreOp`.
+enum Undo {
+    Create { id: ObjId, evicted: Option<Object> },
+    Delete(Object),
+    UpdateTags { id: ObjId, added: Vec<String>, removed: Vec<String> },
+}
+
+/// Apply `ops` in order under a single lock, all-or-nothing.
+///
+/// If any op fails, every op already applied is undone in reverse order
+/// and the error is returned, leaving objects and indices as they were.
+/// Restored objects keep every field; only their LRU recency and their
+/// position in tag and metadata query results may differ.
+pub fn transaction(ops: &[StoreOp]) -> Result<(), ObjError> {
+    let now = rtc::now();
+    mutate(|store| apply_all(store, ops, now))
+}
+
+fn apply_all(store: &mut StoreInner, ops: &[StoreOp], now: DateTime) -> Result<(), ObjError> {
+    store.writable()?;
+    let mut undo = Vec::new();
+    // A rolled-back transaction reports nothing
+    let mark = store.events.len();
+
+    for op in ops {
+        let applied = match op {
+            StoreOp::Create(obj) => store
+                .insert(obj.clone(), now)
+                .map(|(id, evicted)| Undo::Create { id, evicted }),
+            StoreOp::Delete(id) if store.is_pinned(*id) => Err(ObjError::InUse),
+            StoreOp::Delete(id) => store
+                .remove(*id)
+                .map(Undo::Delete)
+                .ok_or(ObjError::NotFound),
+            StoreOp::UpdateTags { id, add, remove } => store
+                .retag(*id, add, remove)
+                .map(|(added, removed)| {
+                    store.events.push(StoreEvent::Updated(*id));
+                    Undo::UpdateTags { id: *id, added, removed }
+                }),
+        };
+
+        match applied {
+            Ok(u) => undo.push(u),
+            Err(e) => {
+                for u in undo.into_iter().rev() {
+                    match u {
+                        Undo::Create { id, evicted } => {
+                            store.remove(id);
+                            if let Some(obj) = evicted {
+                                store.reinsert(obj);
+                            }
+                        }
+                        Undo::Delete(obj) => store.reinsert(obj),
+                        Undo::UpdateTags { id, added, removed } => {
+                            let _ = store.retag_unchecked(id, &removed, &added);
+                        }
+                    }
+                }
+                store.events.truncate(mark);
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Serialize every object, in insertion order, to a flat buffer.
+///
+/// Layout (little-endian, strings and byte runs u32-length-prefixed):
+/// object count `u32`, then per object its id `u64`, content, tag count
+/// `u32` and tags, metadata count `u32` and key/value pairs, and a
+/// `chunked` byte. Version links, timestamps, TTLs and LRU state are not
+/// saved.
+pub fn export() -> Vec<u8> {
+    let store = STORE.lock();
+    let mut objects: Vec<&Object> = store.objects.as_ref()
+        .map_or_else(Vec::new, |m| m.values().collect());
+    objects.sort_unstable_by_key(|o| o.inserted_seq);
+
+    let mut w = Writer::new();
+    w.u32(objects.len() as u32);
+    for obj in objects {
+        w.u64(obj.id.0);
+        w.bytes(&obj.content);
+        w.u32(obj.tags.len() as u32);
+        for tag in &obj.tags {
+            w.str(tag);
+        }
+        w.u32(obj.metadata.len() as u32);
+        for (key, val) in &obj.metadata {
+            w.str(key);
+            w.str(val);
+        }
+        w.u8(obj.chunked as u8);
+    }
+    w.finish()
+}
+
+/// Load objects produced by `export`, rebuilding the tag and metadata
+/// indices. Returns how many objects were loaded.
+///
+/// The whole buffer is decoded before anything is stored; a truncated or
+/// malformed buffer fails with `Corrupt`. Objects are then inserted as one
+/// `transaction`, so a clash with an existing object (`AlreadyExists`)
+/// loads nothing. Ids are taken from the buffer as-is, since appended
+/// objects no longer match their content hash.
+pub fn import(buf: &[u8]) -> Result<usize, ObjError> {
+    let ops = decode(&mut Reader::new(buf)).ok_or(ObjError::Corrupt)?;
+    let count = ops.len();
+    transaction(&ops)?;
+    Ok(count)
+}
+
+fn decode(r: &mut Reader) -> Option<Vec<StoreOp>> {
+    let count = r.u32()?;
+    let mut ops = Vec::new();
+    for _ in 0..count {
+        let id = ObjId(r.u64()?);
+        let mut obj = Object::new(r.bytes()?);
+        obj.id = id;
+        for _ in 0..r.u32()? {
+            obj.tags.push(r.string()?);
+        }
+        for _ in 0..r.u32()? {
+            let key = r.string()?;
+            obj.metadata.insert(key, r.string()?);
+        }
+        obj.chunked = r.u8()? != 0;
+        ops.push(StoreOp::Create(obj));
+    }
+    r.is_empty().then_some(ops)
 }
 
 /// Query objects matching a tag. Returns list of IDs.
+///
+/// Expired objects carrying the tag are deleted and left out.
 pub fn query_by_tag(tag: &str) -> Vec<ObjId> {
+    mutate(|store| {
+        let ids = store.tag_index.as_ref()
+            .and_then(|idx| idx.get(tag))
+            .cloned()
+            .unwrap_or_default();
+        ids.into_iter().filter(|&id| !store.expire(id)).collect()
+    })
+}
+
+/// Number of objects carrying `tag`.
+///
+/// Like `query_by_tag`, expired objects carrying the tag are deleted and
+/// not counted.
+pub fn count_by_tag(tag: &str) -> usize {
+    mutate(|store| {
+        let ids = store.tag_index.as_ref()
+            .and_then(|idx| idx.get(tag))
+            .cloned()
+            .unwrap_or_default();
+        ids.into_iter().filter(|&id| !store.expire(id)).count()
+    })
+}
+
+/// All tags starting with `prefix`, with their object ids, in tag order.
+///
+/// Range-scans the tag index from `prefix` and stops at the first tag
+/// that no longer matches, so only matching entries are visited.
+/// An empty prefix returns every tag. Expired objects are deleted and
+/// left out, and a tag left with no live objects is dropped.
+pub fn query_tag_prefix(prefix: &str) -> Vec<(String, Vec<ObjId>)> {
+    use core::ops::Bound;
+
+    mutate(|store| {
+        let matches: Vec<(String, Vec<ObjId>)> = store.tag_index.as_ref().map_or_else(Vec::new, |idx| {
+            idx.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+                .take_while(|(tag, _)| tag.starts_with(prefix))
+                .map(|(tag, ids)| (tag.clone(), ids.clone()))
+                .collect()
+        });
+        matches.into_iter()
+            .map(|(tag, ids)| (tag, ids.into_iter().filter(|&id| !store.expire(id)).collect::<Vec<_>>()))
+            .filter(|(_, ids)| !ids.is_empty())
+            .collect()
+    })
+}
+
+/// Query objects whose metadata has `key` set to `val`. Returns list of IDs.
+///
+/// Expired matches are deleted and left out, as in `query_by_tag`.
+pub fn query_by_meta(key: &str, val: &str) -> Vec<ObjId> {
+    mutate(|store| {
+        let ids = store.meta_index.as_ref()
+            .and_then(|idx| idx.get(&(String::from(key), String::from(val))))
+            .cloned()
+            .unwrap_or_default();
+        ids.into_iter().filter(|&id| !store.expire(id)).collect()
+    })
+}
+
+/// Objects whose content contains `needle`, in id order. An empty needle
+/// matches every object.
+///
+/// This is a linear scan of all stored content, not an index lookup:
+/// O(total content size) per call, with the store locked throughout.
+/// Expired matches are deleted and left out, as in `query_by_tag`.
+pub fn query_by_content(needle: &[u8]) -> Vec<ObjId> {
+    mutate(|store| {
+        let ids: Vec<ObjId> = store.objects.as_ref().map_or_else(Vec::new, |m| {
+            m.values()
+                .filter(|o| needle.is_empty() || o.content.windows(needle.len()).any(|w| w == needle))
+                .map(|o| o.id)
+                .collect()
+        });
+        ids.into_iter().filter(|&id| !store.expire(id)).collect()
+    })
+}
+
+/// List every tag in use with the number of objects carrying it.
+pub fn list_tags() -> Vec<(String, usize)> {
     let store = STORE.lock();
-    store.tag_index.as_ref()
-        .and_then(|idx| idx.get(tag))
-        .cloned()
-        .unwrap_or_default()
+    store.tag_index.as_ref().map_or_else(Vec::new, |idx| {
+        idx.iter()
+            .filter(|(_, ids)| !ids.is_empty())
+            .map(|(tag, ids)| (tag.clone(), ids.len()))
+            .collect()
+    })
 }
 
-/// Delete an object by ID.
-pub fn delete(id: ObjId) -> Result<(), ObjError> {
-    let mut store = STORE.lock();
-    let obj = store.objects()
-        .remove(&id)
-        .ok_or(ObjError::NotFound)?;
+/// Delete an object by ID. Returns the store `version` right after.
+///
+/// A chunk still listed by a manifest fails with `InUse`.
+pub fn delete(id: ObjId) -> Result<u64, ObjError> {
+    let (removed, version) = mutate_versioned(|store| {
+        store.writable()?;
+        if store.is_pinned(id) {
+            return Err(ObjError::InUse);
+        }
+        store.remove(id).ok_or(ObjError::NotFound)
+    });
+    removed.map(|_| version)
+}
 
-    // Clean up tag index
-    for tag in &obj.tags {
-        if let Some(ids) = store.tag_index().get_mut(tag) {
-            ids.retain(|i| *i != id);
+/// Delete every object carrying `tag`, returning how many were deleted.
+///
+/// Each object is dropped from all of its tag and metadata index entries,
+/// not just `tag`'s. Chunks still listed by a manifest are kept.
+pub fn delete_by_tag(tag: &str) -> Result<usize, ObjError> {
+    mutate(|store| {
+        store.writable()?;
+        let mut ids = store.tag_index().get(tag).cloned().unwrap_or_default();
+        ids.retain(|&id| !store.is_pinned(id));
+        for &id in &ids {
+            store.remove(id);
         }
-    }
-    Ok(())
+        Ok(ids.len())
+    })
+}
+
+/// Objects stored strictly after timer tick `tick`, in id order.
+pub fn query_created_after(tick: u64) -> Vec<ObjId> {
+    let store = STORE.lock();
+    store.objects.as_ref().map_or_else(Vec::new, |m| {
+        m.values()
+            .filter(|o| o.created_tick > tick)
+            .map(|o| o.id)
+            .collect()
+    })
+}
+
+/// Every object id in the order the objects were inserted, oldest first.
+///
+/// Independent of the hash values, so it gives reproducible iteration.
+/// Deleting an object leaves the survivors' order unchanged.
+pub fn list_by_insertion() -> Vec<ObjId> {
+    let store = STORE.lock();
+    let mut entries: Vec<(u64, ObjId)> = store.objects.as_ref().map_or_else(Vec::new, |m| {
+        m.values().map(|o| (o.inserted_seq, o.id)).collect()
+    });
+    entries.sort_unstable();
+    entries.into_iter().map(|(_, id)| id).collect()
+}
+
+/// Re-hash `id`'s stored content and check it still matches the id.
+///
+/// Returns `Ok(false)` if the content was corrupted in memory. Objects
+/// grown with `append` keep their original id, so they report `false`
+/// here too; callers storing appendable objects should expect that.
+/// Does not affect LRU recency.
+pub fn verify_integrity(id: ObjId) -> Result<bool, ObjError> {
+    let store = STORE.lock();
+    store.objects.as_ref()
+        .and_then(|m| m.get(&id))
+        .map(|obj| ObjId::from_content(&obj.content) == id)
+        .ok_or(ObjError::NotFound)
+}
+
+/// Every object whose content no longer hashes to its id, in id order.
+/// Includes appended objects; see `verify_integrity`.
+pub fn verify_all() -> Vec<ObjId> {
+    let store = STORE.lock();
+    store.objects.as_ref().map_or_else(Vec::new, |m| {
+        m.iter()
+            .filter(|(id, obj)| ObjId::from_content(&obj.content) != **id)
+            .map(|(id, _)| *id)
+            .collect()
+    })
+}
+
+/// Delete every object whose TTL has run out, except pinned chunks.
+/// Returns how many were deleted, which is always 0 while the store is
+/// frozen.
+pub fn sweep_expired() -> usize {
+    mutate(|store| {
+        if store.frozen {
+            return 0;
+        }
+        let now = ticks();
+        let expired: Vec<ObjId> = store.objects.as_ref().map_or_else(Vec::new, |m| {
+            m.values()
+                .filter(|o| o.is_expired(now) && !store.is_pinned(o.id))
+                .map(|o| o.id)
+                .collect()
+        });
+        for &id in &expired {
+            store.remove(id);
+        }
+        expired.len()
+    })
 }
 
 /// Count of objects in the store.
+///
+/// Expired objects are only removed when accessed or swept, so this can
+/// overcount until `sweep_expired` runs.
 pub fn count() -> usize {
     let store = STORE.lock();
     store.objects.as_ref().map_or(0, |m| m.len())
@@ -1,33 +1,220 @@
-//! Serial port output for QEMU debugging.
+//! Serial port I/O for QEMU debugging.
 
+use core::sync::atomic::{AtomicU16, Ordering};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+/// I/O base of each supported UART: COM1, COM2.
+const PORT_BASES: [u16; 2] = [0x3F8, 0x2F8];
+
+/// Number of serial ports available through `port`.
+pub const PORT_COUNT: usize = PORT_BASES.len();
+
+/// UART input clock divided by 16: the baud rate at divisor 1.
+pub const UART_BASE_BAUD: u32 = 115_200;
+
+/// Divisor used by `init` (38400 baud).
+pub const DEFAULT_DIVISOR: u16 = 3;
+
+/// Console UART base and divisor, read when `PORTS` is first built.
+static CONSOLE_BASE: AtomicU16 = AtomicU16::new(PORT_BASES[0]);
+static CONSOLE_DIVISOR: AtomicU16 = AtomicU16::new(DEFAULT_DIVISOR);
+
+/// Divisor latch value for `baud`, or None if the rate can't be produced
+/// exactly (zero, above `UART_BASE_BAUD`, or not a whole divisor).
+pub const fn divisor_for(baud: u32) -> Option<u16> {
+    if baud == 0 || baud > UART_BASE_BAUD || !UART_BASE_BAUD.is_multiple_of(baud) {
+        return None;
+    }
+    Some((UART_BASE_BAUD / baud) as u16)
+}
+
+/// Program the 16550 at `base` for `divisor` and 8N1 framing.
+///
+/// The divisor latch shares its registers with data and interrupt-enable,
+/// so the order matters: interrupts off, set DLAB, write the divisor,
+/// clear DLAB while setting the line format, then FIFOs, modem control,
+/// and finally interrupts back on.
+///
+/// # Safety
+/// `base` must be the I/O base of a 16550-compatible UART.
+unsafe fn program(base: u16, divisor: u16) {
+    let mut data = Port::<u8>::new(base);
+    let mut int_en = Port::<u8>::new(base + 1);
+    let mut fifo_ctrl = Port::<u8>::new(base + 2);
+    let mut line_ctrl = Port::<u8>::new(base + 3);
+    let mut modem_ctrl = Port::<u8>::new(base + 4);
+    let [lo, hi] = divisor.to_le_bytes();
+    unsafe {
+        int_en.write(0x00);
+        line_ctrl.write(0x80); // DLAB on
+        data.write(lo);
+        int_en.write(hi);
+        line_ctrl.write(0x03); // DLAB off; 8 data bits, no parity, 1 stop bit
+        fifo_ctrl.write(0xC7); // enable and clear FIFOs, 14-byte threshold
+        modem_ctrl.write(0x0B); // DTR, RTS, OUT2 (IRQ line)
+        int_en.write(0x01); // receive-data interrupt
+    }
+}
+
+fn open(base: u16, divisor: u16) -> Mutex<SerialPort> {
+    unsafe { program(base, divisor) };
+    // SerialPort only holds the base; `program` already set it up
+    Mutex::new(unsafe { SerialPort::new(base) })
+}
 
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
-        Mutex::new(serial_port)
-    };
+    /// Every UART, each behind its own lock. Index 0 is the console
+    /// (COM1 unless `init_with` chose another), where `print!`/`println!`
+    /// go; index 1 is COM2.
+    static ref PORTS: [Mutex<SerialPort>; PORT_COUNT] = [
+        open(CONSOLE_BASE.load(Ordering::Relaxed), CONSOLE_DIVISOR.load(Ordering::Relaxed)),
+        open(PORT_BASES[1], DEFAULT_DIVISOR),
+    ];
 }
 
-/// Initialize serial output.
+/// The kernel console (COM1 by default).
+pub fn serial1() -> &'static Mutex<SerialPort> {
+    &PORTS[0]
+}
+
+/// Serial port `n` (0 = console, 1 = COM2), or None if out of range.
+///
+/// Callers locking a port directly must do so inside `without_interrupts`,
+/// as `_print` does, so an interrupt handler that prints can't deadlock.
+pub fn port(n: usize) -> Option<&'static Mutex<SerialPort>> {
+    PORTS.get(n)
+}
+
+/// Initialize serial output on COM1 at 38400 baud.
 pub fn init() {
+    init_with(PORT_BASES[0], DEFAULT_DIVISOR);
+}
+
+/// Initialize serial output with the console on the UART at `port_base`,
+/// running at `UART_BASE_BAUD / divisor` baud (see `divisor_for`).
+///
+/// Must be called before anything prints: the ports are set up on first
+/// use, and later calls change nothing. COM2 always uses the default rate.
+pub fn init_with(port_base: u16, divisor: u16) {
+    CONSOLE_BASE.store(port_base, Ordering::Relaxed);
+    CONSOLE_DIVISOR.store(divisor.max(1), Ordering::Relaxed);
     // Serial is initialized lazily, just force it here
-    let _ = SERIAL1.lock();
+    lazy_static::initialize(&PORTS);
+}
+
+/// Read one byte from the UART if one is waiting.
+pub fn read_byte() -> Option<u8> {
+    // Same reentrancy guard as `_print`: never hold the lock across an IRQ
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        serial1().lock().try_receive().ok()
+    })
+}
+
+/// Block until a full line is read or `buf` fills up.
+///
+/// The terminating `\r` or `\n` is consumed but not stored. Returns the
+/// number of bytes written to `buf`.
+pub fn read_line(buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    while len < buf.len() {
+        match read_byte() {
+            Some(b'\r') | Some(b'\n') => break,
+            Some(byte) => {
+                buf[len] = byte;
+                len += 1;
+            }
+            None => core::hint::spin_loop(),
+        }
+    }
+    len
+}
+
+/// Print `bytes` as a classic 16-bytes-per-line hex dump (see `HexDump`).
+pub fn hexdump(bytes: &[u8]) {
+    crate::print!("{}", HexDump(bytes));
+}
+
+/// Formats as a classic 16-bytes-per-line hex dump: offset, hex pairs
+/// (split into two groups of 8), and a printable-ASCII gutter. A final
+/// partial line is padded so the gutter stays aligned.
+pub struct HexDump<'a>(pub &'a [u8]);
+
+impl core::fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for (line, chunk) in self.0.chunks(16).enumerate() {
+            write!(f, "{:08x} ", line * 16)?;
+            for i in 0..16 {
+                if i == 8 {
+                    write!(f, " ")?;
+                }
+                match chunk.get(i) {
+                    Some(b) => write!(f, " {:02x}", b)?,
+                    None => write!(f, "   ")?,
+                }
+            }
+            write!(f, "  |")?;
+            for &b in chunk {
+                let c = if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' };
+                write!(f, "{}", c)?;
+            }
+            writeln!(f, "|")?;
+        }
+        Ok(())
+    }
+}
+
+/// Echo serial input back line by line, forever (manual QEMU check).
+#[cfg(feature = "serial-echo")]
+pub fn echo_loop() -> ! {
+    let mut buf = [0u8; 128];
+    loop {
+        crate::print!("> ");
+        let len = read_line(&mut buf);
+        let text = core::str::from_utf8(&buf[..len]).unwrap_or("(bin)");
+        crate::println!("{}", text);
+    }
 }
 
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
+    _print_to(0, args);
+}
+
+#[doc(hidden)]
+pub fn _print_to(n: usize, args: ::core::fmt::Arguments) {
     use core::fmt::Write;
+    let port = port(n).expect("no such serial port");
     // Disable interrupts while holding the serial lock to prevent
     // deadlock if a timer/keyboard interrupt fires mid-print.
     x86_64::instructions::interrupts::without_interrupts(|| {
-        SERIAL1.lock().write_fmt(args).expect("Printing to serial failed");
+        port.lock().write_fmt(args).expect("Printing to serial failed");
     });
 }
 
+/// Print from the panic handler without risking a deadlock.
+///
+/// If the console's lock is free it is used as normal. If it is held — the panic
+/// fired while something was printing, or a lock holder was interrupted
+/// — the held lock is left alone and the message goes through a fresh
+/// `SerialPort` handle on the same already-initialized UART, so the panic
+/// message always gets out (possibly interleaved with the cut-off line).
+#[doc(hidden)]
+pub fn _print_panic(args: ::core::fmt::Arguments) {
+    use core::fmt::Write;
+    match serial1().try_lock() {
+        Some(mut port) => {
+            let _ = port.write_fmt(args);
+        }
+        None => {
+            let mut port = unsafe { SerialPort::new(CONSOLE_BASE.load(Ordering::Relaxed)) };
+            let _ = port.write_fmt(args);
+        }
+    }
+}
+
 /// Print to serial (QEMU console).
 #[macro_export]
 macro_rules! print {
@@ -40,3 +227,23 @@ macro_rules! println {
     () => ($crate::print!("\n"));
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
+
+/// Print a line from the panic handler; see `serial::_print_panic`.
+#[macro_export]
+macro_rules! panic_println {
+    () => ($crate::serial::_print_panic(format_args!("\n")));
+    ($($arg:tt)*) => ($crate::serial::_print_panic(format_args!("{}\n", format_args!($($arg)*))));
+}
+
+/// Print to serial port `n` (see `serial::port`).
+#[macro_export]
+macro_rules! print_to {
+    ($n:expr, $($arg:tt)*) => ($crate::serial::_print_to($n, format_args!($($arg)*)));
+}
+
+/// Print to serial port `n` with newline.
+#[macro_export]
+macro_rules! println_to {
+    ($n:expr) => ($crate::print_to!($n, "\n"));
+    ($n:expr, $($arg:tt)*) => ($crate::print_to!($n, "{}\n", format_args!($($arg)*)));
+}
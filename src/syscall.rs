@@ -0,0 +1,208 @@
+//! System-call interface through software interrupt `int 0x80`.
+//!
+//! Everything still runs in one address space, but library OSes can go
+//! through this vector instead of calling kernel functions directly, so
+//! the kernel boundary is explicit.
+//!
+//! ABI: the syscall number goes in `rax` and up to four arguments in
+//! `rdi`, `rsi`, `rdx`, `r10`. The result comes back in `rax`; every other
+//! register is preserved. A failed call returns `-(code)` as a `u64`, so
+//! any value above `u64::MAX - 4095` is an error (see `SysError`).
+//!
+//! Pointer arguments are trusted: there is no user address space to
+//! check them against yet.
+
+use crate::caps::{manager as cap_mgr, CapError, CapId, Resource, Rights};
+use crate::objstore::{gated, gated::GatedError, store, ObjError, ObjId, Object};
+
+/// Interrupt vector reserved for system calls.
+pub const SYSCALL_VECTOR: usize = 0x80;
+
+/// Syscall numbers, passed in `rax`.
+pub mod nr {
+    /// `() -> ticks`: timer ticks since boot.
+    pub const TICKS: u64 = 0;
+    /// `(kind, a, b, rights) -> cap`: mint a non-delegatable capability.
+    /// `kind` is 0 Memory (`a` base, `b` size), 1 Device (`a`),
+    /// 2 Object (`a`) or 3 Cpu (`a` ticks).
+    pub const CAP_MINT: u64 = 1;
+    /// `(cap, rights) -> 0`: check that `cap` grants `rights`.
+    pub const CAP_VERIFY: u64 = 2;
+    /// `(cap, ptr, len) -> id`: store `len` bytes at `ptr` (WRITE).
+    pub const STORE_CREATE: u64 = 3;
+    /// `(cap, id, ptr, len) -> size`: copy up to `len` bytes of the
+    /// object's content to `ptr` and return its full size (READ).
+    pub const STORE_READ: u64 = 4;
+}
+
+/// Error codes, returned negated in `rax`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum SysError {
+    /// Unknown syscall number.
+    NoSys = 1,
+    /// Malformed argument (bad resource kind, rights bits, length).
+    Invalid = 2,
+    /// The capability is missing, revoked, expired or lacks the rights.
+    Denied = 3,
+    /// No such object.
+    NotFound = 4,
+    /// The object (or its id) already exists.
+    Exists = 5,
+}
+
+impl From<CapError> for SysError {
+    fn from(_: CapError) -> Self {
+        SysError::Denied
+    }
+}
+
+impl From<GatedError> for SysError {
+    fn from(e: GatedError) -> Self {
+        match e {
+            GatedError::Cap(e) => e.into(),
+            GatedError::Store(ObjError::NotFound) => SysError::NotFound,
+            GatedError::Store(ObjError::AlreadyExists | ObjError::HashCollision) => SysError::Exists,
+            GatedError::Store(_) => SysError::Invalid,
+        }
+    }
+}
+
+/// Encode a syscall result for `rax`.
+fn encode(result: Result<u64, SysError>) -> u64 {
+    match result {
+        Ok(value) => value,
+        Err(e) => (e as u64).wrapping_neg(),
+    }
+}
+
+/// Decode a value returned in `rax`.
+pub fn decode(raw: u64) -> Result<u64, SysError> {
+    let errors = [SysError::NoSys, SysError::Invalid, SysError::Denied, SysError::NotFound, SysError::Exists];
+    match errors.into_iter().find(|&e| (e as u64).wrapping_neg() == raw) {
+        Some(e) => Err(e),
+        None => Ok(raw),
+    }
+}
+
+/// Run syscall `nr` with its register arguments.
+fn dispatch(nr: u64, args: [u64; 4]) -> Result<u64, SysError> {
+    match nr {
+        nr::TICKS => Ok(crate::arch::interrupts::ticks()),
+        nr::CAP_MINT => {
+            let resource = match args[0] {
+                0 => Resource::Memory { base: args[1], size: args[2] },
+                1 => Resource::Device(u32::try_from(args[1]).map_err(|_| SysError::Invalid)?),
+                2 => Resource::Object(args[1]),
+                3 => Resource::Cpu(args[1]),
+                _ => return Err(SysError::Invalid),
+            };
+            Ok(cap_mgr::mint(resource, rights(args[3])?, false).raw())
+        }
+        nr::CAP_VERIFY => {
+            cap_mgr::verify(CapId::from_raw(args[0]), rights(args[1])?)?;
+            Ok(0)
+        }
+        nr::STORE_CREATE => {
+            let len = usize::try_from(args[2]).map_err(|_| SysError::Invalid)?;
+            if args[1] == 0 || len > store::MAX_OBJECT_SIZE {
+                return Err(SysError::Invalid);
+            }
+            // SAFETY: pointer arguments are trusted (see module docs)
+            let content = unsafe { core::slice::from_raw_parts(args[1] as *const u8, len) };
+            Ok(gated::create(CapId::from_raw(args[0]), Object::new(content))?.raw())
+        }
+        nr::STORE_READ => {
+            let len = usize::try_from(args[3]).map_err(|_| SysError::Invalid)?;
+            if args[2] == 0 && len > 0 {
+                return Err(SysError::Invalid);
+            }
+            let size = gated::with_object(CapId::from_raw(args[0]), ObjId::from_raw(args[1]), |obj| {
+                let n = obj.content.len().min(len);
+                // SAFETY: pointer arguments are trusted (see module docs)
+                unsafe { core::ptr::copy_nonoverlapping(obj.content.as_ptr(), args[2] as *mut u8, n) };
+                obj.content.len()
+            })?;
+            Ok(size as u64)
+        }
+        _ => Err(SysError::NoSys),
+    }
+}
+
+/// Parse a rights argument, rejecting unknown bits.
+fn rights(bits: u64) -> Result<Rights, SysError> {
+    u32::try_from(bits)
+        .ok()
+        .and_then(Rights::from_bits)
+        .ok_or(SysError::Invalid)
+}
+
+/// Caller-saved registers, in the order `entry` pushes them (last first).
+#[repr(C)]
+struct SyscallFrame {
+    rax: u64,
+    rcx: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    r11: u64,
+}
+
+extern "C" fn dispatch_frame(frame: &mut SyscallFrame) {
+    let args = [frame.rdi, frame.rsi, frame.rdx, frame.r10];
+    frame.rax = encode(dispatch(frame.rax, args));
+}
+
+/// IDT entry point for `SYSCALL_VECTOR`.
+///
+/// Saves the caller-saved registers, hands them to `dispatch_frame`, and
+/// restores them with the result in `rax`. The CPU leaves `rsp` 8 bytes
+/// off 16-byte alignment after pushing its 5-word frame; the 9 pushes
+/// here bring it back to 16 for the call.
+#[unsafe(naked)]
+pub extern "C" fn entry() {
+    core::arch::naked_asm!(
+        "push r11",
+        "push r10",
+        "push r9",
+        "push r8",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push rcx",
+        "push rax",
+        "mov rdi, rsp",
+        "cld",
+        "call {dispatch}",
+        "pop rax",
+        "pop rcx",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "pop r8",
+        "pop r9",
+        "pop r10",
+        "pop r11",
+        "iretq",
+        dispatch = sym dispatch_frame,
+    );
+}
+
+/// Invoke syscall `nr` through `int 0x80` and decode the result.
+pub fn invoke(nr: u64, args: [u64; 4]) -> Result<u64, SysError> {
+    let raw: u64;
+    unsafe {
+        core::arch::asm!(
+            "int 0x80",
+            inlateout("rax") nr => raw,
+            in("rdi") args[0],
+            in("rsi") args[1],
+            in("rdx") args[2],
+            in("r10") args[3],
+        );
+    }
+    decode(raw)
+}
@@ -0,0 +1,87 @@
+//! Inter-task message passing over bounded channels.
+//!
+//! `send` and `recv` never block. A task waiting for a message can use
+//! `Receiver::recv_or_sleep`, which puts it to sleep and retries the same
+//! step later when the channel is empty.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use super::scheduler;
+
+/// Shared channel state.
+struct Channel {
+    queue: Mutex<VecDeque<Vec<u8>>>,
+    capacity: usize,
+}
+
+/// Sending half of a channel. Cloning yields another sender.
+#[derive(Clone)]
+pub struct Sender {
+    chan: Arc<Channel>,
+}
+
+/// Receiving half of a channel.
+pub struct Receiver {
+    chan: Arc<Channel>,
+}
+
+/// The channel was full; the rejected message is handed back.
+#[derive(Debug)]
+pub struct Full(pub Vec<u8>);
+
+impl core::fmt::Display for Full {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "channel full")
+    }
+}
+
+/// Create a bounded FIFO channel holding at most `capacity` messages.
+pub fn channel(capacity: usize) -> (Sender, Receiver) {
+    let chan = Arc::new(Channel {
+        queue: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+    });
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+impl Sender {
+    /// Queue a message, or return it in `Full` if the channel is at capacity.
+    pub fn send(&self, msg: Vec<u8>) -> Result<(), Full> {
+        let mut queue = self.chan.queue.lock();
+        if queue.len() >= self.chan.capacity {
+            return Err(Full(msg));
+        }
+        queue.push_back(msg);
+        Ok(())
+    }
+}
+
+impl Receiver {
+    /// Take the oldest message, if any.
+    pub fn recv(&self) -> Option<Vec<u8>> {
+        self.chan.queue.lock().pop_front()
+    }
+
+    /// Number of messages waiting.
+    pub fn len(&self) -> usize {
+        self.chan.queue.lock().len()
+    }
+
+    /// Whether no messages are waiting.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take the oldest message, or — when called from a step function —
+    /// sleep for `poll_ticks` and rerun the same step once woken.
+    pub fn recv_or_sleep(&self, poll_ticks: u64) -> Option<Vec<u8>> {
+        let msg = self.recv();
+        if msg.is_none() {
+            scheduler::sleep_current(poll_ticks);
+            scheduler::yield_now();
+        }
+        msg
+    }
+}
@@ -2,12 +2,13 @@
 //!
 //! Tasks are lightweight units of execution. Each task has a "step"
 //! function that gets called repeatedly. The scheduler gives each
-//! task a fuel budget (timer ticks) and switches to the next task
-//! when fuel runs out.
+//! task a fuel budget (timer ticks) and a weighted share of steps per
+//! turn, and switches to the next task when either runs out.
 //!
 //! Tasks hold capabilities — they start with zero and must be
 //! explicitly granted access.
 
+pub mod ipc;
 pub mod scheduler;
 
 use alloc::vec::Vec;
@@ -15,7 +16,7 @@ use core::sync::atomic::{AtomicU64, Ordering};
 use crate::caps::CapId;
 
 /// Unique task identifier.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TaskId(u64);
 
 static NEXT_ID: AtomicU64 = AtomicU64::new(0);
@@ -37,6 +38,10 @@ impl core::fmt::Display for TaskId {
 pub enum TaskState {
     Ready,
     Running,
+    /// Not runnable until `ticks()` reaches `until`.
+    Sleeping { until: u64 },
+    /// Out of CPU budget (see `Scheduler::spawn_gated`); never runs again.
+    Suspended,
     Done,
 }
 
@@ -49,6 +54,11 @@ pub struct Task {
     pub total_steps: u64,
     pub step_fn: fn(u64, &[CapId]),  // Called with (step_index, caps)
     pub caps: Vec<CapId>,            // Capabilities held by this task
+    pub fuel: u64,                   // Fuel budget per scheduler turn
+    pub weight: u32,                 // Steps credited per turn (DRR)
+    pub deficit: u64,                // Unspent step credit carried between turns
+    pub deadline_tick: u64,          // EDF deadline (u64::MAX = none)
+    pub cpu_ticks: Option<u64>,      // Remaining CPU-cap budget (None = unmetered)
 }
 
 impl Task {
@@ -62,6 +72,16 @@ impl Task {
             total_steps,
             step_fn,
             caps,
+            fuel: scheduler::DEFAULT_FUEL,
+            weight: 1,
+            deficit: 0,
+            deadline_tick: u64::MAX,
+            cpu_ticks: None,
         }
     }
+
+    /// Whether this task holds `cap`.
+    pub fn has_cap(&self, cap: CapId) -> bool {
+        self.caps.contains(&cap)
+    }
 }
@@ -1,13 +1,32 @@
-//! Round-robin cooperative scheduler.
+//! Weighted round-robin cooperative scheduler.
 //!
-//! Each task has a step function that gets called once per scheduler turn.
-//! Tasks hold capabilities that are passed to the step function.
+//! Sharing is deficit round-robin: each turn a task is credited its
+//! `weight` in steps and runs until that credit, its fuel, or its steps
+//! run out, so over time tasks get steps in proportion to their weights.
+//! Fuel is refilled to the task's budget at the start of its turn and
+//! drains by one per timer tick and one per completed step; credit left
+//! over when fuel cuts a turn short carries to the next turn (at most one
+//! turn's worth). Tasks hold capabilities that are passed to the step
+//! function. Tasks spawned with `spawn_gated` are also metered by a CPU
+//! capability and suspended once its tick budget is spent. `run_forever`
+//! turns the scheduler into an event loop that halts while idle.
+//!
+//! Ordering: a task is requeued at the back after its turn, and a sleeper
+//! rejoins at the back when it wakes, behind every task already waiting,
+//! so waking never lets a task jump the queue. That queue order is what
+//! `TieBreak::Fifo` (the default) picks by; `TieBreak::ById` ignores it
+//! and cycles through runnable tasks in id order instead.
+//!
+//! This is voluntary-checkpoint preemption: fuel is only checked between
+//! steps, so a step that never returns still monopolizes the CPU. True
+//! preemption of a running step needs context switching (out of scope).
 
-use alloc::collections::VecDeque;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::vec::Vec;
-use core::sync::atomic::{AtomicU64, Ordering};
-use super::{Task, TaskState};
-use crate::caps::CapId;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use super::{Task, TaskId, TaskState};
+use spin::Mutex;
+use crate::caps::{self, CapError, CapId, Resource, Rights};
 use crate::println;
 
 /// Default fuel (timer ticks) per task slice.
@@ -16,11 +35,30 @@ pub const DEFAULT_FUEL: u64 = 18;
 /// Global fuel counter.
 static FUEL_REMAINING: AtomicU64 = AtomicU64::new(DEFAULT_FUEL);
 
-/// Called from timer interrupt handler.
-pub fn timer_tick() {
-    let remaining = FUEL_REMAINING.load(Ordering::Relaxed);
-    if remaining > 0 {
-        FUEL_REMAINING.fetch_sub(1, Ordering::Relaxed);
+/// Timer ticks elapsed during the running task's turn, for CPU-cap debits.
+static TURN_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Whether a `run`/`run_edf` loop currently has tasks to schedule.
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+/// Called from timer interrupt handler. Returns false if no scheduler was
+/// running tasks when the tick arrived.
+pub fn timer_tick() -> bool {
+    burn(1);
+    TURN_TICKS.fetch_add(1, Ordering::Relaxed);
+    BUSY.load(Ordering::Relaxed)
+}
+
+/// Consume fuel, saturating at zero.
+fn burn(amount: u64) {
+    let mut fuel = FUEL_REMAINING.load(Ordering::Relaxed);
+    while let Err(current) = FUEL_REMAINING.compare_exchange_weak(
+        fuel,
+        fuel.saturating_sub(amount),
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+    ) {
+        fuel = current;
     }
 }
 
@@ -29,58 +67,549 @@ pub fn fuel_exhausted() -> bool {
     FUEL_REMAINING.load(Ordering::Relaxed) == 0
 }
 
-/// Reset fuel.
-pub fn refuel() {
-    FUEL_REMAINING.store(DEFAULT_FUEL, Ordering::Relaxed);
+/// Sentinel meaning "no sleep requested".
+const NO_SLEEP: u64 = u64::MAX;
+
+/// Wake-up tick requested by the running task's step, if any.
+static SLEEP_REQUEST: AtomicU64 = AtomicU64::new(NO_SLEEP);
+
+/// Put the current task to sleep for `ticks` timer ticks.
+///
+/// Called from a step function; takes effect when the step returns and
+/// ends the task's turn. A sleep of 0 re-readies the task immediately.
+pub fn sleep_current(ticks: u64) {
+    let until = crate::arch::interrupts::ticks().saturating_add(ticks);
+    SLEEP_REQUEST.store(until.min(NO_SLEEP - 1), Ordering::Relaxed);
+}
+
+/// Take and clear any pending sleep request.
+fn take_sleep_request() -> Option<u64> {
+    match SLEEP_REQUEST.swap(NO_SLEEP, Ordering::Relaxed) {
+        NO_SLEEP => None,
+        until => Some(until),
+    }
+}
+
+/// Set by `yield_now` during a step; observed and cleared by the scheduler.
+static YIELD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Give up the rest of the current turn.
+///
+/// Called from a step function. The step is not counted as completed, so
+/// the same step index runs again on the task's next turn — useful for
+/// polling loops such as waiting on the keyboard queue.
+pub fn yield_now() {
+    YIELD_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Kills requested from inside step functions, applied after each step.
+static KILL_REQUESTS: Mutex<Vec<TaskId>> = Mutex::new(Vec::new());
+
+/// Ask the running scheduler to terminate a task — including the caller's
+/// own task. Called from a step function; takes effect when the step
+/// returns, via the same path as `Scheduler::kill`.
+pub fn request_kill(id: TaskId) {
+    KILL_REQUESTS.lock().push(id);
+}
+
+/// Release a finished or killed task's capability references.
+///
+/// Caps held by a task are treated as owned references (see
+/// `caps::manager::clone_ref`), so a task leaving the scheduler drops
+/// them however it ends.
+fn release_caps(task: &mut Task) {
+    task.state = TaskState::Done;
+    for cap in task.caps.drain(..) {
+        let _ = caps::manager::drop_ref(cap);
+    }
+}
+
+/// Reset fuel to the given budget (the current task's).
+pub fn refuel(budget: u64) {
+    FUEL_REMAINING.store(budget, Ordering::Relaxed);
+}
+
+/// Whether a queued task may be given a turn now. Sleepers are woken by
+/// `Scheduler::wake_due` before each pick, so any still asleep wait.
+fn is_runnable(task: &Task) -> bool {
+    !matches!(task.state, TaskState::Sleeping { .. } | TaskState::Suspended)
+}
+
+/// Charge the ticks elapsed since the last debit to a metered task.
+/// Returns true once its CPU budget is spent.
+fn debit_cpu(task: &mut Task) -> bool {
+    let used = TURN_TICKS.swap(0, Ordering::Relaxed);
+    match task.cpu_ticks.as_mut() {
+        Some(left) => {
+            *left = left.saturating_sub(used);
+            *left == 0
+        }
+        None => false,
+    }
+}
+
+/// How `run` and the event loop choose between runnable tasks. `run_edf`
+/// always breaks deadline ties by lowest task id, whatever the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TieBreak {
+    /// The task with the next id after the last task to run, wrapping
+    /// around. Independent of queue order, so the same task set always
+    /// runs in the same sequence.
+    ById,
+    /// The task nearest the front of the queue.
+    #[default]
+    Fifo,
 }
 
 /// The cooperative round-robin scheduler.
 pub struct Scheduler {
     tasks: VecDeque<Task>,
+    stats: SchedStats,
+    policy: TieBreak,
+    /// Task given the most recent turn, for `TieBreak::ById`.
+    last_run: Option<TaskId>,
+}
+
+/// Counters accumulated across `run` calls.
+#[derive(Debug, Clone, Default)]
+pub struct SchedStats {
+    /// Turns given to tasks (sleeping tasks skipped don't count).
+    pub turns: u64,
+    /// Steps completed across all tasks (yielded steps don't count).
+    pub steps: u64,
+    /// Steps completed per task.
+    pub per_task: BTreeMap<TaskId, u64>,
+    /// EDF turns that started after the task's deadline.
+    pub missed: u64,
+    /// Times `run_forever`/`run_until_idle` halted with nothing runnable.
+    pub idle: u64,
 }
 
 impl Scheduler {
-    /// Create a new empty scheduler.
+    /// Create a new empty scheduler with the default `TieBreak::Fifo`.
     pub fn new() -> Self {
+        Self::with_policy(TieBreak::default())
+    }
+
+    /// Create a new empty scheduler that breaks ties by `policy`.
+    pub fn with_policy(policy: TieBreak) -> Self {
         Scheduler {
             tasks: VecDeque::new(),
+            stats: SchedStats::default(),
+            policy,
+            last_run: None,
         }
     }
 
     /// Spawn a new task with capabilities.
-    pub fn spawn(&mut self, name: &'static str, steps: u64, caps: Vec<CapId>, step_fn: fn(u64, &[CapId])) {
+    ///
+    /// The task takes over one reference to each cap and drops it when it
+    /// completes or is killed; pass `clone_ref`'d caps to keep using them.
+    pub fn spawn(&mut self, name: &'static str, steps: u64, caps: Vec<CapId>, step_fn: fn(u64, &[CapId])) -> TaskId {
         let task = Task::new(name, steps, step_fn, caps);
+        let id = task.id;
         println!("[SCHED] Spawned {} ({}, {} steps)", task.name, task.id, steps);
         self.tasks.push_back(task);
+        id
+    }
+
+    /// Spawn a task with a scheduling weight (at least 1): it is credited
+    /// `weight` steps per turn, where `spawn` gives weight 1.
+    pub fn spawn_with_weight(
+        &mut self,
+        name: &'static str,
+        steps: u64,
+        weight: u32,
+        caps: Vec<CapId>,
+        step_fn: fn(u64, &[CapId]),
+    ) -> TaskId {
+        let id = self.spawn(name, steps, caps, step_fn);
+        if let Some(t) = self.task_mut(id) {
+            t.weight = weight.max(1);
+        }
+        id
+    }
+
+    /// Spawn a task metered by a CPU-time capability.
+    ///
+    /// `cpu_cap` must be an EXECUTE capability over `Resource::Cpu(ticks)`;
+    /// the task may then run for `ticks` timer ticks in total, after which
+    /// it is `Suspended`. Ticks are debited as they elapse during the
+    /// task's turns, checked after each step.
+    pub fn spawn_gated(
+        &mut self,
+        cpu_cap: CapId,
+        name: &'static str,
+        steps: u64,
+        caps: Vec<CapId>,
+        step_fn: fn(u64, &[CapId]),
+    ) -> Result<TaskId, CapError> {
+        let budget = match caps::manager::describe(cpu_cap)? {
+            (Resource::Cpu(ticks), _) => ticks,
+            _ => return Err(CapError::PermissionDenied),
+        };
+        caps::manager::verify(cpu_cap, Rights::EXECUTE)?;
+
+        let id = self.spawn(name, steps, caps, step_fn);
+        if let Some(t) = self.task_mut(id) {
+            t.cpu_ticks = Some(budget);
+        }
+        Ok(id)
+    }
+
+    /// Set a task's per-turn fuel budget (at least 1). Returns false if
+    /// no such task is queued.
+    pub fn set_fuel(&mut self, id: TaskId, budget: u64) -> bool {
+        match self.task_mut(id) {
+            Some(t) => {
+                t.fuel = budget.max(1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the tick a queued task should finish by, for `run_edf`.
+    /// Returns false if no such task is queued.
+    pub fn set_deadline(&mut self, id: TaskId, deadline_tick: u64) -> bool {
+        match self.task_mut(id) {
+            Some(t) => {
+                t.deadline_tick = deadline_tick;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Give a queued task an additional capability, handing it the
+    /// caller's reference. Granting one it already holds changes nothing
+    /// but drops the now-redundant reference. Returns false if no such
+    /// task is queued.
+    pub fn grant(&mut self, task: TaskId, cap: CapId) -> bool {
+        match self.task_mut(task) {
+            Some(t) => {
+                if t.has_cap(cap) {
+                    let _ = caps::manager::drop_ref(cap);
+                } else {
+                    t.caps.push(cap);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove a capability from a queued task, dropping its reference.
+    /// Returns false if the task is unknown or does not hold `cap`.
+    pub fn revoke_from(&mut self, task: TaskId, cap: CapId) -> bool {
+        match self.task_mut(task) {
+            Some(t) => match t.caps.iter().position(|c| *c == cap) {
+                Some(pos) => {
+                    t.caps.remove(pos);
+                    let _ = caps::manager::drop_ref(cap);
+                    true
+                }
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Move `cap` from one queued task to another.
+    ///
+    /// The reference moves with it, so the refcount is unchanged — unless
+    /// `to` already holds the cap, in which case the now-redundant source
+    /// reference is dropped. Transferring to the same task is a no-op.
+    /// Fails with `NotFound`, changing nothing, if either task is unknown
+    /// or `from` does not hold the cap.
+    pub fn transfer(&mut self, cap: CapId, from: TaskId, to: TaskId) -> Result<(), CapError> {
+        let src = self.tasks.iter().position(|t| t.id == from).ok_or(CapError::NotFound)?;
+        let dst = self.tasks.iter().position(|t| t.id == to).ok_or(CapError::NotFound)?;
+        let held = self.tasks[src].caps.iter().position(|c| *c == cap).ok_or(CapError::NotFound)?;
+        if src == dst {
+            return Ok(());
+        }
+
+        self.tasks[src].caps.remove(held);
+        if self.tasks[dst].has_cap(cap) {
+            let _ = caps::manager::drop_ref(cap);
+        } else {
+            self.tasks[dst].caps.push(cap);
+        }
+        Ok(())
+    }
+
+    fn task_mut(&mut self, id: TaskId) -> Option<&mut Task> {
+        self.tasks.iter_mut().find(|t| t.id == id)
+    }
+
+    /// Turn and step counters so far.
+    pub fn stats(&self) -> SchedStats {
+        self.stats.clone()
+    }
+
+    /// State of a queued task, or None if it is unknown or finished.
+    ///
+    /// The task running a step is popped out of the queue for the duration
+    /// of its turn and is not visible here; in practice `run` holds the
+    /// scheduler exclusively, so callers only ever observe it between runs.
+    pub fn task_state(&self, id: TaskId) -> Option<TaskState> {
+        self.tasks.iter().find(|t| t.id == id).map(|t| t.state)
+    }
+
+    /// Snapshot of queued tasks as `(id, name, state, current_step, total_steps)`,
+    /// in queue order. Same visibility rules as `task_state`.
+    pub fn list(&self) -> Vec<(TaskId, &'static str, TaskState, u64, u64)> {
+        self.tasks
+            .iter()
+            .map(|t| (t.id, t.name, t.state, t.current_step, t.total_steps))
+            .collect()
+    }
+
+    /// Terminate a queued task, dropping its capability references.
+    /// Returns false if no such task is queued.
+    pub fn kill(&mut self, id: TaskId) -> bool {
+        match self.tasks.iter().position(|t| t.id == id) {
+            Some(pos) => {
+                if let Some(mut task) = self.tasks.remove(pos) {
+                    release_caps(&mut task);
+                    println!("[SCHED] {} killed", task.name);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Apply kills requested during a step. Returns true if the running
+    /// task (not in the queue) was among them.
+    fn apply_kill_requests(&mut self, running: TaskId) -> bool {
+        let requests: Vec<TaskId> = KILL_REQUESTS.lock().drain(..).collect();
+        let mut killed_running = false;
+        for id in requests {
+            if id == running {
+                killed_running = true;
+            } else {
+                self.kill(id);
+            }
+        }
+        killed_running
     }
 
-    /// Run all tasks in round-robin order until all are done.
+    /// Run all tasks in weighted round-robin order until all are done.
     pub fn run(&mut self) {
         println!("[SCHED] Starting scheduler with {} tasks", self.tasks.len());
         println!();
 
-        while !self.tasks.is_empty() {
-            if let Some(mut task) = self.tasks.pop_front() {
-                task.state = TaskState::Running;
-                refuel();
+        BUSY.store(true, Ordering::Relaxed);
+        while self.has_live_tasks() {
+            self.wake_due();
+            // Sleeping tasks are skipped until their deadline passes
+            let Some(task) = self.pick(false).and_then(|pos| self.tasks.remove(pos)) else {
+                core::hint::spin_loop();
+                continue;
+            };
+            self.run_turn(task);
+        }
+        BUSY.store(false, Ordering::Relaxed);
+
+        self.print_summary();
+    }
+
+    /// Run all tasks earliest-deadline-first until all are done.
+    ///
+    /// Each turn goes to the runnable task with the smallest
+    /// `deadline_tick`, ties broken by task id. A turn that starts after
+    /// the task's deadline still runs, but counts towards `SchedStats::missed`.
+    pub fn run_edf(&mut self) {
+        println!("[SCHED] Starting EDF scheduler with {} tasks", self.tasks.len());
+        println!();
+
+        BUSY.store(true, Ordering::Relaxed);
+        while self.has_live_tasks() {
+            self.wake_due();
+            let Some(task) = self.pick(true).and_then(|pos| self.tasks.remove(pos)) else {
+                // Everything is asleep
+                core::hint::spin_loop();
+                continue;
+            };
 
-                // Run exactly one step, passing the task's capabilities
-                if task.current_step < task.total_steps {
-                    (task.step_fn)(task.current_step, &task.caps);
-                    task.current_step += 1;
+            if crate::arch::interrupts::ticks() > task.deadline_tick {
+                self.stats.missed += 1;
+            }
+            self.run_turn(task);
+        }
+        BUSY.store(false, Ordering::Relaxed);
+
+        self.print_summary();
+        println!("[SCHED] {} turns started past their deadline", self.stats.missed);
+    }
+
+    /// Run tasks as an event loop that never returns.
+    ///
+    /// Like `run`, but when no task is runnable — all asleep, or none
+    /// left — the CPU halts until the next interrupt and then checks
+    /// again, instead of spinning or returning.
+    pub fn run_forever(&mut self) -> ! {
+        println!("[SCHED] Starting event loop with {} tasks", self.tasks.len());
+        println!();
+        loop {
+            self.event_step();
+        }
+    }
+
+    /// `run_forever`, but return after `max_idle` halts with nothing
+    /// runnable, for test runs. Returns the number of halts.
+    pub fn run_until_idle(&mut self, max_idle: u64) -> u64 {
+        println!("[SCHED] Starting event loop with {} tasks (idle limit {})", self.tasks.len(), max_idle);
+        println!();
+        let mut halts = 0;
+        while halts < max_idle {
+            if !self.event_step() {
+                halts += 1;
+            }
+        }
+        BUSY.store(false, Ordering::Relaxed);
+        self.print_summary();
+        println!("[SCHED] {} idle halts", halts);
+        halts
+    }
+
+    /// Queue position of the runnable task to run next: earliest
+    /// `deadline_tick` then lowest id if `by_deadline`, else by `TieBreak`.
+    fn pick(&self, by_deadline: bool) -> Option<usize> {
+        self.tasks
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| is_runnable(t))
+            .min_by_key(|(pos, t)| {
+                if by_deadline {
+                    return (t.deadline_tick, false, t.id.0);
                 }
+                match self.policy {
+                    // Ids after the last one run sort first, then wrap around
+                    TieBreak::ById => (0, self.last_run.is_some_and(|last| t.id <= last), t.id.0),
+                    TieBreak::Fifo => (0, false, *pos as u64),
+                }
+            })
+            .map(|(pos, _)| pos)
+    }
 
-                if task.current_step >= task.total_steps {
-                    task.state = TaskState::Done;
-                    println!("[SCHED] {} completed", task.name);
-                } else {
-                    task.state = TaskState::Ready;
-                    self.tasks.push_back(task);
+    /// Give the next runnable task a turn, or halt until an interrupt if
+    /// there is none. Returns whether a task ran.
+    fn event_step(&mut self) -> bool {
+        self.wake_due();
+        if let Some(task) = self.pick(false).and_then(|pos| self.tasks.remove(pos)) {
+            BUSY.store(true, Ordering::Relaxed);
+            self.run_turn(task);
+            return true;
+        }
+
+        BUSY.store(self.has_live_tasks(), Ordering::Relaxed);
+        self.stats.idle += 1;
+        // sti;hlt as one step: an IRQ between enabling and halting would
+        // otherwise be handled first, leaving the CPU asleep until the next
+        x86_64::instructions::interrupts::enable_and_hlt();
+        false
+    }
+
+    /// Make every sleeper whose wake tick has arrived `Ready` again and
+    /// move it to the back of the queue, in the order they were queued.
+    ///
+    /// A woken task thus runs after every task already waiting, rather
+    /// than taking whatever queue position it slept in.
+    fn wake_due(&mut self) {
+        let now = crate::arch::interrupts::ticks();
+        let mut i = 0;
+        let mut unchecked = self.tasks.len();
+        while unchecked > 0 {
+            unchecked -= 1;
+            match self.tasks[i].state {
+                TaskState::Sleeping { until } if now >= until => {
+                    if let Some(mut task) = self.tasks.remove(i) {
+                        task.state = TaskState::Ready;
+                        self.tasks.push_back(task);
+                    }
                 }
+                _ => i += 1,
             }
         }
+    }
+
+    /// Give `task` one turn, then requeue it or retire it.
+    fn run_turn(&mut self, mut task: Task) {
+        task.state = TaskState::Running;
+        self.last_run = Some(task.id);
+        self.stats.turns += 1;
+        refuel(task.fuel);
+        take_sleep_request();
+        YIELD_REQUESTED.store(false, Ordering::Relaxed);
+        TURN_TICKS.store(0, Ordering::Relaxed);
+
+        task.deficit += u64::from(task.weight);
 
+        // Run steps until credit or fuel runs out, passing the task's capabilities
+        while task.current_step < task.total_steps && task.deficit > 0 {
+            (task.step_fn)(task.current_step, &task.caps);
+            let yielded = YIELD_REQUESTED.swap(false, Ordering::Relaxed);
+            if !yielded {
+                task.deficit -= 1;
+                task.current_step += 1;
+                self.stats.steps += 1;
+                *self.stats.per_task.entry(task.id).or_default() += 1;
+            }
+            burn(1);
+            if self.apply_kill_requests(task.id) {
+                release_caps(&mut task);
+                break;
+            }
+            if debit_cpu(&mut task) {
+                task.state = TaskState::Suspended;
+                break;
+            }
+            if let Some(until) = take_sleep_request() {
+                task.state = TaskState::Sleeping { until };
+                break;
+            }
+            if yielded || fuel_exhausted() {
+                break;
+            }
+        }
+
+        if task.state == TaskState::Done {
+            println!("[SCHED] {} killed", task.name);
+        } else if task.current_step >= task.total_steps {
+            release_caps(&mut task);
+            println!("[SCHED] {} completed", task.name);
+        } else {
+            if task.state == TaskState::Suspended {
+                println!("[SCHED] {} suspended (CPU budget spent)", task.name);
+            }
+            if task.state == TaskState::Running {
+                task.state = TaskState::Ready;
+            }
+            task.deficit = task.deficit.min(u64::from(task.weight));
+            self.tasks.push_back(task);
+        }
+    }
+
+    /// Whether any queued task can still run (suspended tasks can't).
+    fn has_live_tasks(&self) -> bool {
+        self.tasks.iter().any(|t| t.state != TaskState::Suspended)
+    }
+
+    fn print_summary(&self) {
         println!();
         println!("[SCHED] All tasks completed");
+        let suspended = self.tasks.len();
+        if suspended > 0 {
+            println!("[SCHED] {} tasks left suspended", suspended);
+        }
+        println!(
+            "[SCHED] {} turns, {} steps across {} tasks",
+            self.stats.turns,
+            self.stats.steps,
+            self.stats.per_task.len(),
+        );
     }
 }
@@ -75,3 +75,8 @@ echo "  UEFI: $OUT_DIR/paryantaos-uefi.img"
 echo ""
 echo "Run with QEMU:"
 echo "  qemu-system-x86_64 -drive format=raw,file=$OUT_DIR/paryantaos-bios.img -serial stdio -display none"
+echo ""
+echo "For a kernel built with --features qemu-test, add the exit device (status 33 = pass):"
+echo "  qemu-system-x86_64 -drive format=raw,file=$OUT_DIR/paryantaos-bios.img -serial stdio -display none \\"
+echo "    -device isa-debug-exit,iobase=0xf4,iosize=0x04"
+echo "(Build with --features qemu-test,stack-overflow-demo to check the double fault stack instead.)"